@@ -1,26 +1,567 @@
-// use log::info;
-// use tokio::sync::mpsc::Receiver;
-// use tokio::sync::watch;
-// use crate::history::History;
-// use crate::observations::Observation;
-// use crate::value::Value;
-
-// const PROCESS_BUFFER_LIMIT: usize = 100;
-
-// pub async fn coordinator(init_consensus: Option<Value>, mut receive: Receiver<Observation>, w_tx: watch::Sender<Option<Value>>) -> ! {
-//     let mut history = History::new(); // Initialise empty O(V).
-//     let mut observations = Vec::with_capacity(PROCESS_BUFFER_LIMIT); // Input buffer to read observations.
-//
-//     loop {
-//         receive.recv_many(&mut observations, PROCESS_BUFFER_LIMIT).await; // Recieve all pending observations into buffer.
-//
-//         for observation in observations.drain(0..observations.len()) {
-//             history.add_new(observation); // For each observation, add it to O(V).
-//         }
-//
-//         let new_value = history.apply(init_consensus);
-//
-//         info!("Coordinator - New Value: {:?}", new_value);
-//         w_tx.send(new_value).unwrap();
-//     }
-// }
\ No newline at end of file
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::{error, info, warn};
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::{interval, Instant};
+use crate::error::Error;
+use crate::inference::{Inference, Outcome, Snapshot};
+use crate::inference::explain::Explanation;
+use crate::inference::interval::{Interval, Moment, TimeDistribution};
+use crate::journal::{live, Entry, Journal};
+use crate::observations::{DefinitionPredicate, Observation, ObservationId, Report, SourceKind};
+use crate::value::{Quantity, States, Stock, Target, Value};
+
+const PROCESS_BUFFER_LIMIT: usize = 100;
+const BROADCAST_BUFFER: usize = 1024;
+const QUERY_BUFFER: usize = 16;
+
+// Delivers a published value to a subscription derived from it - false once nobody receives it.
+type View<V> = Box<dyn Fn(&Target, Option<V>) -> bool + Send>;
+
+// Consensus per target - writers subscribe to the targets they serve, or to every target.
+#[derive(Clone)]
+pub struct Publisher<V = Value> {
+    targets: Arc<Mutex<HashMap<Target, watch::Sender<Option<V>>>>>,
+    all: broadcast::Sender<(Target, Option<V>)>,
+    views: Arc<Mutex<Vec<View<V>>>>,
+}
+
+impl<V: Quantity> Publisher<V> {
+    pub fn new() -> Self {
+        let (all, _) = broadcast::channel(BROADCAST_BUFFER);
+        Publisher { targets: Arc::new(Mutex::new(HashMap::new())), all, views: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    pub fn subscribe(&self, target: Target) -> watch::Receiver<Option<V>> {
+        let mut targets = self.targets.lock().unwrap();
+        targets.entry(target).or_insert_with(|| watch::channel(None).0).subscribe()
+    }
+
+    pub fn subscribe_all(&self) -> broadcast::Receiver<(Target, Option<V>)> {
+        self.all.subscribe()
+    }
+
+    fn publish(&self, target: &Target, value: Option<V>) {
+        let mut targets = self.targets.lock().unwrap();
+        targets.entry(target.clone()).or_insert_with(|| watch::channel(None).0).send_replace(value);
+        let _ = self.all.send((target.clone(), value)); // Fine if nobody listens to every target.
+        self.views.lock().unwrap().retain(|view| view(target, value));
+    }
+}
+
+impl<Q: Quantity> Publisher<States<Q>> {
+    // Consensus of one state of a target - only changes when that state's quantity does.
+    // Delivered as each value is published - dropped at the next once nobody receives it.
+    pub fn subscribe_state(&self, target: Target, stock: Stock) -> watch::Receiver<Option<Q>> {
+        let current = self.targets.lock().unwrap().get(&target).and_then(|states| states.borrow().map(|s| s[stock]));
+        let (send, receive) = watch::channel(current);
+        self.views.lock().unwrap().push(Box::new(move |published, states| {
+            if *published == target {
+                let value = states.map(|s| s[stock]);
+                send.send_if_modified(|current| std::mem::replace(current, value) != value);
+            }
+            !send.is_closed()
+        }));
+        receive
+    }
+}
+
+// What a target was counted at as this run began - by a source polling it, between sent and replied.
+#[derive(Debug, Clone)]
+pub struct Counted<V = Value> {
+    pub value: Option<V>, // None if its observers counted differently.
+    pub source: SourceKind,
+    pub sent: Moment,
+    pub replied: Moment,
+}
+
+// Asks the coordinator why a target holds its value - answered between batches of reports.
+pub type Query<V> = (Target, oneshot::Sender<Option<Explanation<V>>>);
+
+#[derive(Clone)]
+pub struct Explainer<V = Value> {
+    send: mpsc::Sender<Query<V>>,
+}
+
+impl<V: Quantity> Explainer<V> {
+    pub fn new() -> (Self, Receiver<Query<V>>) {
+        let (send, receive) = mpsc::channel(QUERY_BUFFER);
+        (Explainer { send }, receive)
+    }
+
+    // None if the target was never observed, or the coordinator has stopped.
+    pub async fn explain(&self, target: Target) -> Option<Explanation<V>> {
+        let (reply, answer) = oneshot::channel();
+        self.send.send((target, reply)).await.ok()?;
+        answer.await.ok().flatten()
+    }
+}
+
+struct TargetState<H, V> {
+    history: H,
+    init: Option<V>,
+    last_seen: Instant,
+    checkpointed: Option<Moment>, // Fold its latest journaled snapshot was taken at.
+}
+
+// Sources observing each target, and how to make a history of one - nothing folds until each source has reported.
+struct Histories<'a, H, F: Fn() -> H> {
+    new_history: F,
+    expected: &'a HashMap<Target, Vec<SourceKind>>,
+}
+
+// Target's state - resumed from where it last started, or unknown if it never has.
+fn target_state<'a, V: Quantity, H: Inference<V>>(
+    targets: &'a mut HashMap<Target, TargetState<H, V>>,
+    target: &Target,
+    starts: &HashMap<Target, Snapshot<V>>,
+    histories: &Histories<H, impl Fn() -> H>
+) -> &'a mut TargetState<H, V> {
+    targets.entry(target.clone()).or_insert_with(|| {
+        let mut history = (histories.new_history)();
+        for source in histories.expected.get(target).into_iter().flatten() {
+            history.expect(source.clone());
+        }
+        let start = starts.get(target).cloned().unwrap_or(Snapshot::initial(None));
+        let (init, checkpointed) = (start.value, start.folded_until);
+        history.restore(start);
+        TargetState { history, init, last_seen: Instant::now(), checkpointed }
+    })
+}
+
+fn ingest<V: Quantity, H: Inference<V>>(history: &mut H, report: Report<V>) -> Result<Outcome, Error> {
+    match report {
+        Report::Observed(observation) => history.add_new(observation),
+        Report::Retracted(retraction) => history.retract(retraction),
+        Report::Deviation(source, deviation) => history.deviation(source, deviation).map(|_| Outcome::Accepted),
+        Report::Horizon(source, horizon) => history.advance(source, horizon).map(|_| Outcome::Accepted),
+    }
+}
+
+// Rebuilds every target the journal knows of, compacting away entries behind their snapshots.
+// Targets it has never seen start from their count - journaled, so that after a restart they still do.
+// Those it has may have changed while we were down - reconciled with their count before anything is published.
+// Returns what each target was last published at.
+fn recover<V: Quantity, H: Inference<V>>(
+    journal: &mut Journal,
+    targets: &mut HashMap<Target, TargetState<H, V>>,
+    initial: HashMap<Target, Counted<V>>,
+    starts: &mut HashMap<Target, Snapshot<V>>,
+    histories: &Histories<H, impl Fn() -> H>,
+    publisher: &Publisher<V>
+) -> Result<HashMap<Target, Option<V>>, Error> {
+    let entries = live(journal.replay()?);
+    journal.compact(&entries)?;
+    let known: HashSet<Target> = entries.iter().map(|entry| entry.target().clone()).collect();
+    let (recounted, new): (HashMap<_, _>, HashMap<_, _>) = initial.into_iter().partition(|(target, _)| known.contains(target));
+    for (target, count) in new {
+        journal.append(&Entry::Snapshot(target.clone(), Snapshot::initial(count.value)))?;
+        starts.insert(target, Snapshot::initial(count.value));
+    }
+
+    let mut published = HashMap::new();
+    let mut reached: HashMap<Target, Moment> = HashMap::new(); // Latest any journaled observation of each target ends.
+    for entry in entries {
+        match entry {
+            Entry::Snapshot(target, snapshot) => {
+                let held = snapshot.pending.iter().filter_map(|r| match r { Report::Observed(o) => Some(o.interval.1), _ => None });
+                if let Some(until) = held.chain(snapshot.folded_until).max() {
+                    reached.insert(target.clone(), until);
+                }
+                targets.remove(&target);
+                starts.insert(target, snapshot);
+            }
+            Entry::Observation(target, observation) => {
+                let until = reached.entry(target.clone()).or_insert(observation.interval.1);
+                *until = max(*until, observation.interval.1);
+                if let Err(e) = target_state(targets, &target, starts, histories).history.add_new(observation) {
+                    error!("Coordinator - Journal holds a rejected Observation for {target:?}: {e}");
+                }
+            }
+            Entry::Retraction(target, retraction) => {
+                if let Err(e) = target_state(targets, &target, starts, histories).history.retract(retraction) {
+                    error!("Coordinator - Journal holds a rejected Retraction for {target:?}: {e}");
+                }
+            }
+            Entry::Deviation(target, source, deviation) => {
+                if let Err(e) = target_state(targets, &target, starts, histories).history.deviation(source, deviation) {
+                    error!("Coordinator - Journal holds a rejected Deviation for {target:?}: {e}");
+                }
+            }
+            Entry::Consensus(target, value) => {
+                published.insert(target, value);
+            }
+        }
+    }
+    // Targets checkpointed while still holding levels were active - they are rebuilt, not left idle.
+    let active: Vec<Target> = starts.iter()
+        .filter(|(target, snapshot)| !targets.contains_key(*target) && snapshot.pending.iter().any(|r| matches!(r, Report::Observed(_))))
+        .map(|(target, _)| target.clone())
+        .collect();
+    for target in active {
+        target_state(targets, &target, starts, histories);
+    }
+
+    // What changed while we were down is in the count - assigned over the downtime, after all that was journaled.
+    // Targets that cannot be reconciled (their observers counted differently) restart unknown, as a new one would.
+    for (target, count) in recounted {
+        let since = reached.get(&target).copied().unwrap_or(count.sent).min(count.sent);
+        let reconciled = count.value.and_then(|v_new| {
+            let downtime = Observation {
+                id: ObservationId::Poll(count.source.clone(), count.sent),
+                definition: DefinitionPredicate::Assignment { v_new },
+                interval: Interval(since, count.replied),
+                source: count.source,
+                compensates: None,
+                distribution: TimeDistribution::Uniform,
+                reported: Some(count.replied),
+                timestamp: None,
+            };
+            match target_state(targets, &target, starts, histories).history.add_new(downtime.clone()) {
+                Ok(_) => Some(downtime),
+                Err(e) => {
+                    error!("Coordinator - Could not reconcile {target:?} with its count: {e}");
+                    None
+                }
+            }
+        });
+        match reconciled {
+            Some(downtime) => journal.append(&Entry::Observation(target, downtime))?,
+            None => {
+                warn!("Coordinator - {target:?} not reconciled, restarting unknown");
+                targets.remove(&target);
+                let unknown = Snapshot::<V>::initial(None);
+                journal.append(&Entry::Snapshot(target.clone(), unknown.clone()))?;
+                starts.insert(target.clone(), unknown);
+                target_state(targets, &target, starts, histories); // Held - so that it is published unknown.
+            }
+        }
+    }
+    journal.sync()?;
+
+    for (target, state) in targets.iter_mut() {
+        let value = state.history.apply(state.init);
+        if published.get(target).is_some_and(|last| *last != value) {
+            warn!("Coordinator - Recovered {target:?} at {value:?}, last published {:?}", published[target]);
+        }
+        publisher.publish(target, value);
+        published.insert(target.clone(), value);
+    }
+    info!("Coordinator - Recovered {} targets from journal.", targets.len());
+    Ok(published)
+}
+
+// Every accepted observation, retraction and published value is journaled - synced before it is published.
+pub async fn coordinator<V: Quantity, H: Inference<V>>(
+    new_history: impl Fn() -> H,
+    initial: HashMap<Target, Counted<V>>, // Targets missing here (and from the journal) start unknown.
+    expected: HashMap<Target, Vec<SourceKind>>, // Sources each target's watermark waits on.
+    idle_after: Duration,
+    checkpoint_every: Duration,
+    mut journal: Journal,
+    mut receive: Receiver<(Target, Report<V>)>,
+    mut queries: Receiver<Query<V>>,
+    publisher: Publisher<V>
+) -> Result<(), Error> {
+    let mut targets: HashMap<Target, TargetState<H, V>> = HashMap::new(); // Initialise empty O(V) per target.
+    let mut reports = Vec::with_capacity(PROCESS_BUFFER_LIMIT); // Input buffer to read observations.
+    let mut eviction = interval(idle_after);
+    let mut checkpoint = interval(checkpoint_every);
+    let mut starts = HashMap::new(); // Where each target not held in targets resumes from.
+    let histories = Histories { new_history, expected: &expected };
+    let mut published = recover(&mut journal, &mut targets, initial, &mut starts, &histories, &publisher)?;
+
+    loop {
+        tokio::select! {
+            received = receive.recv_many(&mut reports, PROCESS_BUFFER_LIMIT) => { // Recieve all pending observations into buffer.
+                if received == 0 {
+                    info!("Coordinator - Input closed, stopping.");
+                    return Ok(());
+                }
+
+                let mut changed = Vec::new();
+                for (target, report) in reports.drain(0..reports.len()) {
+                    let held = targets.contains_key(&target);
+                    if !held && matches!(report, Report::Horizon(..)) {
+                        continue; // Nothing held to fold.
+                    }
+                    let state = target_state(&mut targets, &target, &starts, &histories);
+                    // For each observation or retraction, apply it to its target's O(V) - or quarantine it.
+                    match ingest(&mut state.history, report.clone()) {
+                        Ok(Outcome::Accepted) => {}
+                        Ok(Outcome::Duplicate) => continue, // Journaled when first accepted.
+                        Err(e) => {
+                            error!("Coordinator - Quarantined {report:?} for {target:?}: {e}");
+                            if !held {
+                                targets.remove(&target); // Nothing accepted - it stays where it resumes from.
+                            }
+                            continue;
+                        }
+                    }
+                    // Horizons are not journaled - but may fold, or bound records their polls saw, and so change the value.
+                    if let Some(entry) = Entry::report(target.clone(), report) {
+                        state.last_seen = Instant::now();
+                        journal.append(&entry)?;
+                    }
+                    if !changed.contains(&target) {
+                        changed.push(target);
+                    }
+                }
+
+                let mut values = Vec::with_capacity(changed.len());
+                for target in changed {
+                    let state = targets.get_mut(&target).unwrap();
+                    let new_value = state.history.apply(state.init);
+                    if published.get(&target) == Some(&new_value) {
+                        continue;
+                    }
+                    published.insert(target.clone(), new_value);
+                    info!("Coordinator - New Value for {target:?}: {new_value:?}");
+                    journal.append(&Entry::Consensus(target.clone(), new_value))?;
+                    values.push((target, new_value));
+                }
+                journal.sync()?;
+                for (target, new_value) in values {
+                    publisher.publish(&target, new_value);
+                }
+            }
+            Some((target, reply)) = queries.recv() => {
+                // An evicted target resumes from its snapshot to be explained - and is evicted again once idle.
+                let known = targets.contains_key(&target) || starts.contains_key(&target);
+                let explanation = known.then(|| {
+                    let state = target_state(&mut targets, &target, &starts, &histories);
+                    state.history.explain(state.init)
+                });
+                let _ = reply.send(explanation); // Fine if the asker has given up.
+            }
+            _ = eviction.tick() => {
+                // Idle targets whose history has wholly folded keep only the snapshot - a returning target resumes from it.
+                // Those still holding levels a late observation could reorder are kept, however long idle.
+                let idle: Vec<Target> = targets.iter()
+                    .filter(|(_, state)| state.last_seen.elapsed() >= idle_after)
+                    .map(|(target, _)| target.clone())
+                    .collect();
+                for target in idle {
+                    let state = targets.get_mut(&target).unwrap();
+                    let Some(snapshot) = state.history.settled(state.init) else { continue };
+                    info!("Coordinator - Evicting idle {target:?} at {:?}", snapshot.value);
+                    targets.remove(&target);
+                    journal.append(&Entry::Snapshot(target.clone(), snapshot.clone()))?;
+                    starts.insert(target, snapshot);
+                }
+                journal.sync()?;
+            }
+            _ = checkpoint.tick() => {
+                // Targets that folded further restart from their snapshot - everything journaled before it is compacted away.
+                let mut checkpointed = false;
+                for (target, state) in targets.iter_mut() {
+                    let Some(snapshot) = state.history.snapshot(state.init) else { continue };
+                    if snapshot.folded_until <= state.checkpointed {
+                        continue;
+                    }
+                    state.checkpointed = snapshot.folded_until;
+                    journal.append(&Entry::Snapshot(target.clone(), snapshot))?;
+                    if let Some(value) = published.get(target) {
+                        journal.append(&Entry::Consensus(target.clone(), *value))?;
+                    }
+                    checkpointed = true;
+                }
+                if checkpointed {
+                    journal.sync()?;
+                    let entries = live(journal.replay::<V>()?);
+                    journal.compact(&entries)?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::time::Duration;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use tokio::time::sleep;
+    use uuid::Uuid;
+    use crate::inference::history::NewHistory;
+    use crate::inference::interval::{Interval, Moment, TimeDistribution};
+    use crate::inference::policy::ConflictPolicyConfig;
+    use crate::journal::{Entry, Journal};
+    use crate::observations::{DefinitionPredicate, Observation, ObservationId, Report, SourceKind};
+    use crate::value::{Quantity, States, Stock, Target};
+    use crate::inference::explain::Explanation;
+    use super::{coordinator, Counted, Explainer, Publisher};
+
+    fn observation(source: SourceKind, definition: DefinitionPredicate, start: u64, end: u64) -> Report {
+        Report::Observed(Observation {
+            id: ObservationId::Sequence(source.clone(), start),
+            definition,
+            interval: Interval(Moment(start), Moment(end)),
+            source,
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: Some(Moment(end)),
+            timestamp: None,
+        })
+    }
+
+    fn sale(n: u64, start: u64, end: u64) -> Report {
+        let Report::Observed(sale) = observation(SourceKind::Polling("Poller".to_string()), DefinitionPredicate::Mutation { delta: -1 }, start, end) else { unreachable!() };
+        Report::Observed(Observation { id: ObservationId::Sequence(sale.source.clone(), n), ..sale })
+    }
+
+    fn counted(value: Option<i64>, at: u64) -> Counted<i64> {
+        Counted { value, source: SourceKind::Polling("Poller".to_string()), sent: Moment(at), replied: Moment(at + 5) }
+    }
+
+    // Runs a coordinator over reports, left idle long enough to evict - then what it journaled, and how it explained the target.
+    async fn run(reports: Vec<Report>) -> (Option<i64>, Vec<Entry>, Option<Explanation<i64>>) {
+        let path = std::env::temp_dir().join(format!("journal-{}.log", Uuid::new_v4()));
+        let ran = run_at(&path, counted(Some(10), 0), reports).await;
+        std::fs::remove_file(&path).unwrap();
+        ran
+    }
+
+    async fn run_at(path: &Path, count: Counted<i64>, reports: Vec<Report>) -> (Option<i64>, Vec<Entry>, Option<Explanation<i64>>) {
+        let target: Target = ("Location".to_string(), "Item".to_string());
+        let (send, receive) = mpsc::channel(16);
+        let (explainer, queries) = Explainer::new();
+        let publisher = Publisher::new();
+        let consensus = publisher.subscribe(target.clone());
+        let running = tokio::spawn(coordinator(
+            || NewHistory::new(ConflictPolicyConfig::Strict.build()),
+            HashMap::from([(target.clone(), count)]),
+            HashMap::new(),
+            Duration::from_millis(20),
+            Duration::from_millis(20),
+            Journal::open(path).unwrap(),
+            receive,
+            queries,
+            publisher.clone()
+        ));
+        for report in reports {
+            send.send((target.clone(), report)).await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+        }
+        let explanation = explainer.explain(target).await;
+        drop(send);
+        running.await.unwrap().unwrap();
+        let value = *consensus.borrow();
+        let entries = Journal::open(path).unwrap().replay().unwrap();
+        (value, entries, explanation)
+    }
+
+    fn folded(entries: &[Entry]) -> Vec<Option<Moment>> {
+        entries.iter().filter_map(|e| match e {
+            Entry::Snapshot(_, snapshot) => Some(snapshot.folded_until),
+            _ => None,
+        }).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_targets_keep_what_could_still_reorder() {
+        let (value, entries, _) = run(vec![sale(0, 0, 10)]).await;
+        assert_eq!(value, Some(9));
+        assert_eq!(folded(&entries), vec![None]); // Only its initial value.
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_targets_evict_what_folded() {
+        let horizon = Report::Horizon(SourceKind::Polling("Poller".to_string()), Moment(20));
+        // Once evicted, a late sale starting before the fold is still rejected - not applied to a bare snapshot.
+        let (value, entries, _) = run(vec![sale(0, 0, 10), horizon, sale(1, 5, 25), sale(2, 30, 40)]).await;
+        assert_eq!(value, Some(8));
+        assert_eq!(folded(&entries).last(), Some(&Some(Moment(20)))); // Whether or not checkpointed first.
+    }
+
+    // A sale the record horizon bounds to the poll that saw it - no longer overlapping the recount, which then comes last.
+    #[tokio::test(start_paused = true)]
+    async fn horizons_publish_what_they_reorder() {
+        let (polling, recording) = (SourceKind::Polling("Square".to_string()), SourceKind::Record("Square".to_string()));
+        let seen = observation(polling, DefinitionPredicate::Transition { v_0: 10, v_1: 9 }, 0, 10);
+        let sold = observation(recording.clone(), DefinitionPredicate::Mutation { delta: -1 }, 0, 100);
+        let recount = observation(SourceKind::Polling("Other".to_string()), DefinitionPredicate::Assignment { v_new: 20 }, 50, 60);
+        let (value, entries, _) = run(vec![seen, sold, recount, Report::Horizon(recording, Moment(100))]).await;
+        assert_eq!(value, Some(20));
+        assert!(entries.iter().any(|entry| matches!(entry, Entry::Consensus(_, Some(20)))), "{entries:?}"); // Journaled too.
+    }
+
+    // Two sales defining their level, then a recount a sale could have come either side of - serialized as support staff would read it.
+    #[tokio::test(start_paused = true)]
+    async fn explains_each_level() {
+        let recount = observation(SourceKind::Polling("Counter".to_string()), DefinitionPredicate::Assignment { v_new: 5 }, 20, 30);
+        let other = observation(SourceKind::Polling("Other".to_string()), DefinitionPredicate::Mutation { delta: -1 }, 5, 15);
+        let (value, _, explanation) = run(vec![sale(0, 0, 10), other, recount, sale(1, 25, 35)]).await;
+        assert_eq!(value, None);
+        let trace: serde_json::Value = serde_json::from_str(&serde_json::to_string(&explanation.unwrap()).unwrap()).unwrap();
+        let levels = trace["levels"].as_array().unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!((&levels[0]["before"], &levels[0]["after"], &levels[0]["resolution"]), (&json!(10), &json!(8), &json!("Defined")));
+        assert_eq!((&levels[1]["before"], &levels[1]["after"]), (&json!(8), &json!(null)));
+        assert_eq!(levels[1]["resolution"]["Conflict"]["conflict"], json!({ "Ambiguous": { "outcomes": [4, 5] } }));
+        assert_eq!(trace["value"], json!(null));
+    }
+
+    #[test]
+    fn state_subscriptions_change_with_their_state() {
+        let (target, other): (Target, Target) = (("Location".to_string(), "Item".to_string()), ("Location".to_string(), "Other".to_string()));
+        let publisher = Publisher::<States<i64>>::new();
+        publisher.publish(&target, Some(States::of(Stock::InStock, 10)));
+        let mut in_stock = publisher.subscribe_state(target.clone(), Stock::InStock);
+        assert_eq!(*in_stock.borrow(), Some(10));
+
+        publisher.publish(&target, States::of(Stock::InStock, 9).combine(States::of(Stock::Sold, 1)));
+        assert!(in_stock.has_changed().unwrap());
+        assert_eq!(*in_stock.borrow_and_update(), Some(9));
+        publisher.publish(&target, States::of(Stock::InStock, 9).combine(States::of(Stock::Sold, 2)));
+        publisher.publish(&other, None);
+        assert!(!in_stock.has_changed().unwrap()); // Only what was sold changed - and another target.
+
+        drop(in_stock);
+        assert_eq!(publisher.views.lock().unwrap().len(), 1);
+        publisher.publish(&target, None);
+        assert!(publisher.views.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn checkpoints_keep_what_is_still_held() {
+        let path = std::env::temp_dir().join(format!("journal-{}.log", Uuid::new_v4()));
+        let horizon = Report::Horizon(SourceKind::Polling("Poller".to_string()), Moment(20));
+        let (value, entries, _) = run_at(&path, counted(Some(10), 0), vec![sale(0, 0, 10), sale(1, 25, 35), horizon]).await;
+        assert_eq!(value, Some(8));
+        // Compacted to the checkpoint - the sale it folded is gone, the one past the fold is held in it.
+        let [Entry::Snapshot(_, snapshot), Entry::Consensus(_, Some(8))] = &entries[..] else { panic!("{entries:?}") };
+        assert_eq!((snapshot.value, snapshot.folded_until), (Some(9), Some(Moment(20))));
+        assert!(matches!(&snapshot.pending[..], [Report::Observed(held)] if held.interval == Interval(Moment(25), Moment(35))));
+
+        let (value, _, _) = run_at(&path, counted(Some(8), 50), vec![]).await; // Restarted from it.
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(value, Some(8));
+    }
+
+    // Sold twice while down - the count after restarting has it, not the journal.
+    #[tokio::test(start_paused = true)]
+    async fn restarts_reconcile_with_their_count() {
+        let path = std::env::temp_dir().join(format!("journal-{}.log", Uuid::new_v4()));
+        let (value, _, _) = run_at(&path, counted(Some(10), 0), vec![sale(0, 10, 20)]).await;
+        assert_eq!(value, Some(9));
+
+        let (value, entries, _) = run_at(&path, counted(Some(7), 100), vec![sale(1, 110, 120)]).await;
+        assert_eq!(value, Some(6));
+        let downtime = entries.iter().find_map(|entry| match entry {
+            Entry::Observation(_, o) if matches!(o.definition, DefinitionPredicate::Assignment { v_new: 7 }) => Some(o.interval),
+            _ => None,
+        });
+        assert_eq!(downtime, Some(Interval(Moment(20), Moment(105)))); // From where the journal left off.
+
+        // Counted differently by its observers - unknown rather than what was last published.
+        let (value, _, _) = run_at(&path, counted(None, 200), vec![]).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(value, None);
+    }
+}
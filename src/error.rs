@@ -0,0 +1,115 @@
+use std::fmt::{Display, Formatter};
+use squareup::models::errors::SquareApiError;
+use crate::inference::interval::{Interval, Moment};
+use crate::observations::{ObservationId, SourceKind};
+use crate::value::Target;
+
+#[derive(Debug)]
+pub enum Error {
+    Inference(InferenceError),
+    Observer(ObserverError),
+    Parse(ParseError),
+    Config(ConfigError),
+    Journal(JournalError),
+}
+
+// An observation the history cannot accept - it is rejected, and the history is left unchanged.
+#[derive(Debug)]
+pub enum InferenceError {
+    InvalidInterval(Interval), // Ends before it starts.
+    OverlappingPolls { source: SourceKind, existing: Interval, new: Interval }, // Two polls sent at once.
+    BehindWatermark { id: ObservationId, folded_until: Moment }, // Starts before what was folded - it could no longer be ordered.
+    Irreversible { id: ObservationId, folded_until: Moment }, // Retracts what was folded as other than a mutation - nothing undoes it.
+    EmptyLevel, // A level holding no observation has nothing to define it.
+    RegionOverlap(Interval), // Interval tree still held a region where one was just removed.
+}
+
+#[derive(Debug)]
+pub enum ObserverError {
+    Client(SquareApiError), // Could not build a client.
+    Platform(SquareApiError), // Request failed.
+    MissingCounts(Target), // Target does not exist on platform!
+    Unsupported(&'static str), // Platform has no such capability.
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Quantity(String),
+    Change(String), // Platform's change lacks what it should carry.
+    Overflow(String), // Quantities combine beyond what the domain can hold.
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Corrupt { offset: usize, resumes: usize }, // Damaged record with intact ones after it - not a torn tail.
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Inference(e) => write!(f, "Inference - {e:?}"),
+            Error::Observer(e) => write!(f, "Observer - {e:?}"),
+            Error::Parse(e) => write!(f, "Parse - {e:?}"),
+            Error::Config(e) => write!(f, "Config - {e:?}"),
+            Error::Journal(e) => write!(f, "Journal - {e:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<InferenceError> for Error {
+    fn from(value: InferenceError) -> Self {
+        Error::Inference(value)
+    }
+}
+
+impl From<ObserverError> for Error {
+    fn from(value: ObserverError) -> Self {
+        Error::Observer(value)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(value: ParseError) -> Self {
+        Error::Parse(value)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(value: ConfigError) -> Self {
+        Error::Config(value)
+    }
+}
+
+impl From<JournalError> for Error {
+    fn from(value: JournalError) -> Self {
+        Error::Journal(value)
+    }
+}
+
+impl From<SquareApiError> for Error {
+    fn from(value: SquareApiError) -> Self {
+        Error::Observer(ObserverError::Platform(value))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Config(ConfigError::Io(value))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Config(ConfigError::Json(value))
+    }
+}
@@ -0,0 +1,42 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use log::debug;
+use crate::observations::{Observation, ObservationId, SourceKind};
+
+// IDs of every observation accepted and not yet folded - refetched observations are dropped, not double counted.
+// Forgotten once folded - a refetch reaching back that far is rejected as behind the watermark instead.
+#[derive(Debug, Default)]
+pub struct Seen {
+    ids: HashSet<ObservationId>,
+    dropped: HashMap<SourceKind, u64>, // Duplicates dropped per source.
+}
+
+impl Seen {
+    pub fn new() -> Self {
+        Seen { ids: HashSet::new(), dropped: HashMap::new() }
+    }
+
+    // Counts the observation as dropped if its ID was accepted before.
+    pub fn repeated<V: Debug>(&mut self, observation: &Observation<V>) -> bool {
+        if !self.ids.contains(&observation.id) {
+            return false;
+        }
+        debug!("Inference - Dropped Duplicate: {observation:?}");
+        *self.dropped.entry(observation.source.clone()).or_default() += 1;
+        true
+    }
+
+    pub fn accept(&mut self, id: ObservationId) {
+        self.ids.insert(id);
+    }
+
+    pub fn forget<'a>(&mut self, ids: impl IntoIterator<Item = &'a ObservationId>) {
+        for id in ids {
+            self.ids.remove(id);
+        }
+    }
+
+    pub fn dropped(&self) -> &HashMap<SourceKind, u64> {
+        &self.dropped
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeSet;
+use serde::Serialize;
+use crate::inference::history::Level;
+use crate::inference::interval::{Interval, Moment};
+use crate::inference::policy::{ConflictPolicy, Likely};
+use crate::inference::retraction::Retracted;
+use crate::observations::{DefinitionPredicate, Observation};
+use crate::value::{Quantity, Value};
+
+// Why the inferred value is what it is - every level, in execution order.
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation<V = Value> {
+    pub folded_until: Option<Moment>, // Levels before this were folded into init.
+    pub init: Option<V>,
+    pub levels: Vec<LevelTrace<V>>,
+    pub retracted: Vec<Retracted<V>>, // Withdrawn observations - in no level above.
+    pub value: Option<V>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LevelTrace<V = Value> {
+    pub interval: Interval,
+    pub observations: Vec<Observation<V>>,
+    pub definition: Option<DefinitionPredicate<V>>,
+    pub before: Option<V>,
+    pub after: Option<V>,
+    pub resolution: Resolution<V>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum Resolution<V = Value> {
+    Defined, // The level's definition applied directly.
+    Ordered, // Every order the level's observations could have happened in agreed.
+    Conflict { conflict: Conflict<V>, policy: String },
+    Likely { conflict: Conflict<V>, likely: Likely<V> }, // Resolved by the order it most likely happened in.
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum Conflict<V = Value> {
+    Mismatch { expected: V, found: Option<V> }, // Transition did not start from the value before it.
+    Ambiguous { outcomes: BTreeSet<Option<V>> }, // Different orders give different values.
+    Impossible, // No order of the level's observations is consistent.
+    Unknown, // Every order leaves the value unknown - only the policy could define it.
+    TooLarge, // Too many observations to consider every order.
+    Overflow, // Applying the level leaves the domain's range.
+}
+
+pub(crate) fn explain<'a, V: Quantity>(
+    levels: impl IntoIterator<Item = &'a Level<V>>,
+    init: Option<V>,
+    folded_until: Option<Moment>,
+    policy: &dyn ConflictPolicy<V>,
+    retracted: &[Retracted<V>]
+) -> Explanation<V> {
+    let mut cumulative = init;
+    let mut build = Vec::new();
+
+    for level in levels {
+        let (after, resolution) = level.resolution(cumulative, policy);
+        build.push(LevelTrace {
+            interval: level.interval,
+            observations: level.observations.clone(),
+            definition: level.definition(),
+            before: cumulative,
+            after,
+            resolution,
+        });
+        cumulative = after;
+    }
+
+    Explanation { folded_until, init, levels: build, retracted: retracted.to_vec(), value: cumulative }
+}
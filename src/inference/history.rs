@@ -1,142 +1,431 @@
-use std::cmp::Ordering;
+use std::cmp::max;
 use std::cmp::Ordering::Less;
-use log::{debug, info};
+use std::collections::{BTreeSet, HashMap};
+use log::{error, info};
 use nodit::NoditMap;
-use petgraph::{Directed, Graph, Undirected};
-use petgraph::acyclic::Acyclic;
-use petgraph::algo::{connected_components, tarjan_scc, toposort};
-use petgraph::data::Build;
-use petgraph::operator::complement;
-use petgraph::prelude::{DiGraph, UnGraph};
-use petgraph::visit::Walker;
-use crate::inference::interval::{Interval, Moment, MERGE};
-use crate::observations::{Observation, DefinitionPredicate};
-use crate::value::Value;
+use crate::error::{Error, InferenceError};
+use crate::inference::{readmit, Inference, Outcome, Snapshot};
+use crate::inference::explain::{explain, Conflict, Explanation, Resolution};
+use crate::inference::interval::{Deviation, Interval, Moment};
+use crate::inference::policy::ConflictPolicy;
+use crate::inference::possible::Possible;
+use crate::inference::propagation::Constraints;
+use crate::inference::query::{value_at, ValueAt};
+use crate::inference::retraction::Retractions;
+use crate::inference::dedup::Seen;
+use crate::inference::watermark::Watermark;
+use crate::observations::{Observation, DefinitionPredicate, ObservationId, Report, Retraction, SourceKind};
+use crate::value::{Quantity, Value};
 
+// Beyond this many observations, enumerating the orders of a level is too costly.
+const ORDERING_LIMIT: usize = 12;
 
-
-pub struct NewHistory {
-    observations: Vec<Observation>,
+#[derive(Debug, Clone)]
+pub struct Level<V = Value> {
+    pub interval: Interval,
+    pub observations: Vec<Observation<V>>
 }
 
-impl NewHistory {
-    pub fn new() -> Self {
-        Self {observations: vec![]}
+impl<V: Quantity> Level<V> {
+    fn new(first: Observation<V>) -> Self {
+        Level {
+            interval: first.interval,
+            observations: Vec::from([first])
+        }
     }
 
-    pub fn add_new(&mut self, observation: Observation) {
-        self.observations.push(observation);
+    fn merge(&mut self, other: Level<V>) {
+        self.observations.extend(other.observations); // When levels merge merge observations
+        self.interval = self.interval.hull(&other.interval); // AND grow interval
     }
 
-    pub fn get_execution(&self) -> Vec<Vec<Observation>> {
-        let mut undirected = UnGraph::new_undirected();
+    // As NewHistory::definition - a level it cannot define is left to its orders, like any other.
+    pub(crate) fn definition(&self) -> Option<DefinitionPredicate<V>> {
+        NewHistory::definition(&self.observations).unwrap_or_else(|e| {
+            error!("Inference - Cannot define {self:?}: {e:?}");
+            None
+        })
+    }
 
-        for observation in self.observations.clone() {
-            undirected.add_node(observation);
-        }
+    fn unordered_with(&self, observation: &Observation<V>) -> bool {
+        self.observations.iter().any(|o| o.ordering(observation).is_none())
+    }
+
+    pub(crate) fn resolve(&self, cumulative: Option<V>, policy: &dyn ConflictPolicy<V>) -> Option<V> {
+        self.resolution(cumulative, policy).0
+    }
 
-        for (i, o_1) in undirected.node_indices().enumerate() {
-            for o_2 in undirected.node_indices().skip(i+1) {
-                match undirected[o_1].partial_cmp(&undirected[o_2]) {
-                    Some(Less) => {undirected.add_edge(o_1, o_2, ());},
-                    _ => continue,
+    // Value after the level, and how it was arrived at.
+    pub(crate) fn resolution(&self, cumulative: Option<V>, policy: &dyn ConflictPolicy<V>) -> (Option<V>, Resolution<V>) {
+        let mismatch = match self.definition() {
+            Some(DefinitionPredicate::Transition { v_0, v_1 }) => {
+                if cumulative.is_some() && cumulative == Some(v_0) {
+                    return (Some(v_1), Resolution::Defined);
                 }
+                Some(v_0)
+            }
+            Some(DefinitionPredicate::Mutation { delta }) => match cumulative.map(|v| v.combine(delta)) {
+                Some(None) => return self.conflicted(Conflict::Overflow, cumulative, policy),
+                after => return (after.flatten(), Resolution::Defined),
             }
+            Some(DefinitionPredicate::Assignment { v_new }) => return (Some(v_new), Resolution::Defined),
+            Some(DefinitionPredicate::Count { stock, v_new }) => return (cumulative.map(|v| v.counted(stock, v_new)), Resolution::Defined),
+            None => None,
+        };
+
+        // The orders still possible within the level may all agree anyway.
+        let conflict = match (self.orders(&BTreeSet::from([cumulative])), mismatch) {
+            (Some(values), _) if values.len() == 1 && values.first().unwrap().is_some() => {
+                return (values.first().cloned().unwrap(), Resolution::Ordered);
+            }
+            (Some(values), _) if values.len() > 1 => Conflict::Ambiguous { outcomes: values },
+            (_, Some(expected)) => Conflict::Mismatch { expected, found: cumulative },
+            (Some(values), None) if values.contains(&None) => Conflict::Unknown,
+            (Some(_), None) => Conflict::Impossible,
+            (None, None) => Conflict::TooLarge,
+        };
+        self.conflicted(conflict, cumulative, policy)
+    }
+
+    // Left to the policy - weighed by likelihood first, if it can.
+    fn conflicted(&self, conflict: Conflict<V>, cumulative: Option<V>, policy: &dyn ConflictPolicy<V>) -> (Option<V>, Resolution<V>) {
+        if let Some(likely) = policy.weigh(self, cumulative) {
+            info!("Inference - {conflict:?} : {self:?} - Likely {:?} ({:.2})", likely.value, likely.confidence);
+            return (likely.value, Resolution::Likely { conflict, likely });
         }
+        let resolved = policy.resolve(self, cumulative);
+        info!("Inference - {conflict:?} : {self:?} - {policy:?} Resolved To {resolved:?}");
+        (resolved, Resolution::Conflict { conflict, policy: format!("{policy:?}") })
+    }
 
-        // Get Graph Complement
-        let mut complement_graph = UnGraph::new_undirected();
-        complement(&undirected, &mut complement_graph, ());
+    // Values reachable by applying the level's observations in every order consistent
+    // with how they are ordered amongst themselves, from any input value.
+    // A None input is an unknown value - only an assignment can define it.
+    // None if the level is too large to enumerate.
+    pub(crate) fn orders(&self, input: &BTreeSet<Option<V>>) -> Option<BTreeSet<Option<V>>> {
+        Some(self.progress(input)?.pop().unwrap())
+    }
 
-        // Get Connected Sections (unorderable regions)
-        let conflicts = tarjan_scc(&complement_graph);
+    // Values part way through the level at moment - after any subset of its observations which
+    // could have happened by then, in an allowed order. None if the level is too large to enumerate.
+    pub(crate) fn partial(&self, input: &BTreeSet<Option<V>>, moment: Moment) -> Option<BTreeSet<Option<V>>> {
+        if self.observations.len() > ORDERING_LIMIT {
+            return None; // Before the masks - they would not fit.
+        }
+        let (mut happened, mut pending) = (0usize, 0usize); // Certainly before, and certainly after, moment.
+        for (i, observation) in self.observations.iter().enumerate() {
+            if observation.interval.1 < moment {
+                happened |= 1 << i;
+            }
+            if observation.interval.0 > moment {
+                pending |= 1 << i;
+            }
+        }
+        let progress = self.progress(input)?;
+        Some(progress.into_iter().enumerate()
+            .filter(|(set, _)| set & happened == happened && set & pending == 0)
+            .flat_map(|(_, values)| values)
+            .collect())
+    }
+
+    // reached[s] - values after applying exactly the observations in bitset s, in any allowed order.
+    fn progress(&self, input: &BTreeSet<Option<V>>) -> Option<Vec<BTreeSet<Option<V>>>> {
+        let n = self.observations.len();
+        if n > ORDERING_LIMIT {
+            return None;
+        }
+
+        // before[i] - bitset of observations which must be applied before observation i.
+        let before: Vec<usize> = self.observations.iter().enumerate().map(|(i, o)| {
+            self.observations.iter().enumerate()
+                .filter(|(j, p)| *j != i && p.ordering(o) == Some(Less))
+                .fold(0, |mask, (j, _)| mask | (1 << j))
+        }).collect();
 
-        // Build directed graph of unorderable regions
-        let mut execution = DiGraph::new();
-        for conflict in conflicts {
-            let mut level: Vec<Observation> = Vec::new();
-            for o in conflict {
-                level.push(complement_graph[o].clone());
+        let mut reached: Vec<BTreeSet<Option<V>>> = vec![BTreeSet::new(); 1 << n];
+        reached[0] = input.clone();
+
+        for set in 0..(1usize << n) {
+            let from = std::mem::take(&mut reached[set]);
+            for (i, observation) in self.observations.iter().enumerate() {
+                if set & (1 << i) != 0 || before[i] & !set != 0 {
+                    continue; // Already applied, or something ordered before it has not been.
+                }
+                for value in &from {
+                    let next = match (observation.definition, value) {
+                        (DefinitionPredicate::Assignment { v_new }, _) => Some(v_new),
+                        (_, None) => None, // Unknown stays unknown.
+                        (definition, Some(v)) => match definition.apply(v) {
+                            Some(next) => Some(next),
+                            None => continue // Transition does not match - this order is impossible.
+                        }
+                    };
+                    reached[set | (1 << i)].insert(next);
+                }
             }
-            execution.add_node(level);
+            reached[set] = from;
         }
 
-        // Generate Edges
-        for (i, o_1) in execution.node_indices().enumerate() {
-            for o_2 in execution.node_indices().skip(i+1) {
-                match execution[o_1][0].partial_cmp(&execution[o_2][0]) {
-                    Some(Less) => {
-                        execution.add_edge(o_1, o_2, ());
-                    },
-                    _ => continue,
+        return Some(reached);
+    }
+
+    // Nothing reachable before the level leaves nothing reachable after it - the history is inconsistent.
+    pub(crate) fn possible(&self, input: &Possible<V>) -> Possible<V> {
+        if matches!(input, Possible::Known(values) if values.is_empty()) {
+            return Possible::Known(BTreeSet::new());
+        }
+        match (self.definition(), input) {
+            // Every order agrees - no need to enumerate.
+            (Some(DefinitionPredicate::Assignment { v_new }), _) => Possible::Known(BTreeSet::from([v_new])),
+            (Some(DefinitionPredicate::Mutation { delta }), Possible::Known(values)) => {
+                Possible::Known(values.iter().filter_map(|v| v.combine(delta)).collect()) // None past the range.
+            }
+            (Some(DefinitionPredicate::Mutation { .. }), _) => input.clone(),
+            _ => match self.orders(&input.inputs()) {
+                None => {
+                    info!("Inference - Level Too Large To Enumerate: {self:?}");
+                    Possible::TooLarge
                 }
+                Some(orders) if orders.contains(&None) => match input {
+                    Possible::TooLarge => Possible::TooLarge,
+                    _ => Possible::Unknown,
+                },
+                Some(orders) => Possible::Known(orders.into_iter().flatten().collect()),
             }
         }
+    }
+}
 
-        // Finally - produce a topological sort of all nodes.
-        // The graph is guaranteed to be acyclic
-        let plan = toposort(&execution, None).unwrap();
-        println!("{:?}", plan);
-        return plan.iter().map(|x| execution[*x].clone()).collect::<Vec<Vec<Observation>>>();
+// Rejects observations the history cannot order - checked before anything is changed.
+// Polls are compared as reported - narrowing may have moved where placed ones start.
+fn validate<V: Quantity>(observation: &Observation<V>, constraints: &Constraints) -> Result<(), InferenceError> {
+    if !observation.interval.is_valid() {
+        return Err(InferenceError::InvalidInterval(observation.interval));
+    }
+    if let SourceKind::Polling(_) = &observation.source {
+        if let Some(existing) = constraints.same_send(observation) {
+            return Err(InferenceError::OverlappingPolls {
+                source: observation.source.clone(),
+                existing,
+                new: observation.interval
+            });
+        }
     }
+    Ok(())
+}
 
+// Observation as the constraints now time it - its narrowed interval, and where in it a stamped change likely happened.
+fn timed<V>(mut observation: Observation<V>, constraints: &Constraints) -> Observation<V> {
+    observation.interval = constraints.interval(&observation.id).unwrap_or(observation.interval);
+    if let Some(distribution) = constraints.distribution(&observation) {
+        observation.distribution = distribution;
+    }
+    observation
+}
 
-    pub fn apply(&self, value: Option<Value>) -> Option<Value> {
-        let execution = self.get_execution();
-        let mut cumulative = value;
+// What a history holds past its fold, as reports rebuilding it on top of the snapshot - clock estimates first,
+// then each observation as reported (readmitted, the constraints narrow it again), then every retraction.
+fn pending<'a, V: Quantity + 'a>(levels: impl Iterator<Item = &'a Level<V>>, constraints: &Constraints, retractions: &Retractions<V>) -> Vec<Report<V>> {
+    let mut pending: Vec<Report<V>> = constraints.deviations().iter()
+        .map(|(source, deviation)| Report::Deviation(source.clone(), *deviation))
+        .collect();
+    for observation in levels.flat_map(|level| level.observations.iter()) {
+        let mut observation = observation.clone();
+        observation.interval = constraints.observed(&observation.id).unwrap_or(observation.interval);
+        pending.push(Report::Observed(observation));
+    }
+    pending.extend(retractions.all().iter().map(|retracted| Report::Retracted(retracted.retraction.clone())));
+    pending
+}
 
-        for level in &execution {
-            match NewHistory::definition(level) {
-                Some(definition) => match definition {
-                    DefinitionPredicate::Transition { v_0, v_1 } => {
-                        if cumulative.is_some() && cumulative == Some(v_0) {
-                            cumulative = Some(v_1);
-                        } else {
-                            cumulative = None;
-                            info!("Inference - Conflict : {level:?}")
-                        }
-                    }
-                    DefinitionPredicate::Mutation { delta } => {
-                        if let Some(v) = cumulative {
-                            cumulative = Some(v + delta);
-                        }
-                    }
-                    DefinitionPredicate::Assignment { v_new } => {
-                        cumulative = Some(v_new);
+// Places an observation into levels (in execution order), considering only levels[from..to].
+// Levels outside that window must already be known to be ordered against it.
+// Returns the index of the first level that changed.
+fn place<V: Quantity>(levels: &mut Vec<Level<V>>, from: usize, to: usize, observation: Observation<V>) -> usize {
+    let mut unordered = (from..to).filter(|i| levels[*i].unordered_with(&observation));
+    match (unordered.next(), unordered.last()) {
+        (Some(first), last) => {
+            // Observation cannot be ordered against these levels - they collapse into one.
+            // Any levels between them are absorbed too, keeping the execution a total order.
+            let last = last.unwrap_or(first);
+            let mut merged = Level::new(observation);
+            for level in levels.drain(first..=last) {
+                merged.merge(level);
+            }
+            levels.insert(first, merged);
+            first
+        }
+        (None, _) => {
+            // Ordered against everything - becomes a new level in its place.
+            let at = from + levels[from..to].partition_point(
+                |level| level.observations[0].ordering(&observation) == Some(Less)
+            );
+            levels.insert(at, Level::new(observation));
+            at
+        }
+    }
+}
+
+pub struct NewHistory<V = Value> {
+    levels: Vec<Level<V>>, // Execution order - maintained as observations arrive.
+    placed: HashMap<ObservationId, Interval>, // Interval each observation was placed at - finds its level from its window.
+    reach: Vec<Moment>, // Running max of level interval ends (levels[..=i]).
+    init: Option<V>, // Value before the first level - the snapshot, once anything is folded.
+    cumulative: Vec<Option<V>>, // Cached value after each level - a prefix of levels.
+    snapshot: Possible<V>, // Possible values before the first level, once anything is folded.
+    possible: Vec<Possible<V>>, // Cached possible values after each level - alongside cumulative.
+    watermark: Watermark,
+    folded_until: Option<Moment>, // Watermark at the last fold - None if nothing folded yet.
+    seen: Seen,
+    retractions: Retractions<V>,
+    constraints: Constraints,
+    policy: Box<dyn ConflictPolicy<V>>,
+}
+
+impl<V: Quantity> NewHistory<V> {
+    pub fn new(policy: Box<dyn ConflictPolicy<V>>) -> Self {
+        Self {
+            levels: vec![], placed: HashMap::new(), reach: vec![], init: None, cumulative: vec![],
+            snapshot: Possible::Unknown, possible: vec![],
+            watermark: Watermark::new(), folded_until: None, seen: Seen::new(), retractions: Retractions::new(),
+            constraints: Constraints::new(), policy
+        }
+    }
+
+    fn update(&mut self, value: Option<V>) {
+        // Once folded, the snapshot has absorbed the initial value - it can no longer change.
+        if self.folded_until.is_none() && value != self.init {
+            self.cumulative.clear(); // Cache was built from a different initial value.
+            self.possible.clear();
+            self.init = value;
+        }
+        let mut cumulative = self.cumulative.last().cloned().unwrap_or(self.init);
+        let mut possible = self.possible.last().cloned().unwrap_or_else(|| self.start());
+
+        for level in &self.levels[self.cumulative.len()..] {
+            cumulative = level.resolve(cumulative, self.policy.as_ref());
+            possible = level.possible(&possible);
+            self.cumulative.push(cumulative);
+            self.possible.push(possible.clone());
+        }
+
+        // Levels wholly before the watermark can never be reordered - fold them into the snapshot.
+        // Nothing can arrive before the watermark anymore either, so it is the fold's whether or not any level was.
+        if let Some(watermark) = self.watermark.get() {
+            let stable = self.reach.partition_point(|end| *end < watermark);
+            if stable > 0 {
+                self.init = self.cumulative[stable - 1];
+                self.snapshot = self.possible[stable - 1].clone();
+                for level in self.levels.drain(..stable) {
+                    for observation in &level.observations {
+                        self.placed.remove(&observation.id);
                     }
+                    self.seen.forget(level.observations.iter().map(|o| &o.id));
+                    self.constraints.forget(level.observations.iter().map(|o| &o.id));
+                    self.retractions.folded(&level.observations, watermark);
                 }
-                None => {
-                    info!("Inference - Undefined Level: {level:?}");
-                    cumulative = None
-                },
+                self.reach.drain(..stable);
+                self.cumulative.drain(..stable);
+                self.possible.drain(..stable);
+            } else if self.folded_until.is_none() {
+                self.snapshot = self.start();
+            }
+            self.folded_until = max(self.folded_until, Some(watermark)); // A restored fold may lie past what sources report.
+            if stable > 0 {
+                self.retractions.prune(self.folded_until.unwrap());
+            }
+        }
+    }
+
+    fn start(&self) -> Possible<V> {
+        match self.folded_until {
+            Some(_) => self.snapshot.clone(),
+            None => self.init.into()
+        }
+    }
+
+    // Levels an observation over interval could be unordered with - levels[from..to].
+    fn window(&self, interval: Interval) -> (usize, usize) {
+        // Levels reaching no further than the observation's start are wholly before it.
+        let from = self.reach.partition_point(|end| *end < interval.0);
+        // Level starts are increasing - those starting after its end are wholly after it.
+        let to = from + self.levels[from..].partition_point(|level| level.interval.0 <= interval.1);
+        (from, to)
+    }
+
+    // Places an observation already validated against the levels it could overlap.
+    fn insert(&mut self, observation: Observation<V>) {
+        self.placed.insert(observation.id.clone(), observation.interval);
+        let (from, to) = self.window(observation.interval);
+        let changed_at = place(&mut self.levels, from, to, observation);
+        self.changed(changed_at);
+    }
+
+    // Removes a placed observation - the rest of its level may no longer be unordered, so each is placed again.
+    fn take(&mut self, id: &ObservationId) -> Option<Observation<V>> {
+        let placed = self.placed.remove(id)?;
+        let (from, to) = self.window(placed); // Its level lies within - it covers where it was placed.
+        let (i, j) = (from..to).find_map(|i| {
+            self.levels[i].observations.iter().position(|o| o.id == *id).map(|j| (i, j))
+        })?;
+        let mut level = self.levels.remove(i);
+        let observation = level.observations.remove(j);
+        self.changed(i);
+        for remaining in level.observations {
+            self.insert(remaining);
+        }
+        Some(observation)
+    }
+
+    // Places again observations whose timing changed - those folded away are left.
+    fn reposition(&mut self, ids: Vec<ObservationId>) {
+        for id in ids {
+            if let Some(observation) = self.take(&id) {
+                self.insert(timed(observation, &self.constraints));
             }
         }
-        return cumulative;
     }
 
-    pub fn definition(level: &Vec<Observation>) -> Option<DefinitionPredicate> {
+    // Only levels from a change onwards need their reach and cached value recomputed.
+    fn changed(&mut self, at: usize) {
+        self.reach.truncate(at);
+        for level in &self.levels[at..] {
+            let end = self.reach.last().map_or(level.interval.1, |r| max(*r, level.interval.1));
+            self.reach.push(end);
+        }
+        self.cumulative.truncate(at);
+        self.possible.truncate(at);
+    }
+
+    pub fn get_execution(&self) -> &[Level<V>] {
+        &self.levels
+    }
+
+    // Single predicate every order of the level's observations agrees on - None if there is none.
+    pub fn definition(level: &Vec<Observation<V>>) -> Result<Option<DefinitionPredicate<V>>, InferenceError> {
         let mut all_mut = true;
-        let mut cumulative_mut = 0;
+        let mut cumulative_mut = V::zero();
         let mut all_last_assn = true;
         let mut value = None;
 
         if level.len() == 0 {
-            panic!("Should be Unreachable!")
+            return Err(InferenceError::EmptyLevel); // Levels are only ever made from an observation.
         }
 
         if level.len() == 1 {
-            return Some(level[0].definition.clone()); // ref XXX
+            return Ok(Some(level[0].definition.clone())); // ref XXX
         }
 
         for observation in level {
             match observation.definition {
-                DefinitionPredicate::Transition {..} => return None, // ref XXX
+                DefinitionPredicate::Transition {..} => return Ok(None), // Chains only in the orders the level allows - see resolution.
+                DefinitionPredicate::Count {..} => return Ok(None), // Does not commute with mutations of its state.
                 DefinitionPredicate::Assignment {v_new} => {
                     all_mut = false;
 
                     if let Some(v) = value {
                         if v != v_new {
-                            return None // Distinct assignments cannot commute - ref XXX
+                            return Ok(None) // Distinct assignments cannot commute - ref XXX
                         }
                     } else {
                         value = Some(v_new); // First assignment witnessed!
@@ -144,111 +433,675 @@ impl NewHistory {
                 },
                 DefinitionPredicate::Mutation {delta} => {
                     all_last_assn = false;
-                    cumulative_mut += delta; // ref XXX
+                    let Some(sum) = cumulative_mut.combine(delta) else {
+                        return Ok(None); // Summed past the range - left to the orders, which may stay within it.
+                    };
+                    cumulative_mut = sum; // ref XXX
                 }
             }
         }
 
         if all_mut {
-            return Some(DefinitionPredicate::Mutation {delta: cumulative_mut });
+            return Ok(Some(DefinitionPredicate::Mutation {delta: cumulative_mut }));
         }
         if all_last_assn {
-            return Some(DefinitionPredicate::Assignment {v_new: value.unwrap()});
+            if let Some(v_new) = value {
+                return Ok(Some(DefinitionPredicate::Assignment { v_new }));
+            }
+        }
+
+        return Ok(None); // Otherwise, no definition could be found!
+    }
+}
+
+impl<V: Quantity> Inference<V> for NewHistory<V> {
+    fn add_new(&mut self, observation: Observation<V>) -> Result<Outcome, Error> {
+        if self.seen.repeated(&observation) {
+            return Ok(Outcome::Duplicate);
+        }
+        if let Some(folded_until) = self.folded_until.filter(|w| observation.interval.0 < *w) {
+            return Err(InferenceError::BehindWatermark { id: observation.id, folded_until }.into());
+        }
+        if self.retractions.withdraws(&observation.id) {
+            return Ok(if self.retractions.arrived(observation) { Outcome::Accepted } else { Outcome::Duplicate });
+        }
+        validate(&observation, &self.constraints)?;
+        self.seen.accept(observation.id.clone());
+        let narrowed = self.constraints.add(&observation);
+        self.insert(timed(observation, &self.constraints));
+        self.reposition(narrowed);
+        Ok(Outcome::Accepted)
+    }
+
+    fn retract(&mut self, retraction: Retraction) -> Result<Outcome, Error> {
+        if self.retractions.withdraws(&retraction.retracts) {
+            return Ok(Outcome::Duplicate); // Retracted already.
+        }
+        match self.take(&retraction.retracts) {
+            Some(observation) => {
+                let changed = self.constraints.remove(&retraction.retracts);
+                self.reposition(changed);
+                self.retractions.record(retraction, Some(observation), self.folded_until);
+            }
+            None => {
+                // Folded already - undone just past the fold, if it can be. Otherwise not arrived yet.
+                if let Some(compensation) = self.folded_until.and_then(|w| self.retractions.compensation(&retraction, w)) {
+                    self.add_new(compensation?)?;
+                }
+                self.retractions.record(retraction, None, self.folded_until);
+            }
+        }
+        Ok(Outcome::Accepted)
+    }
+
+    fn apply(&mut self, value: Option<V>) -> Option<V> {
+        self.update(value);
+        self.cumulative.last().cloned().unwrap_or(self.init)
+    }
+
+    fn possible(&mut self, value: Option<V>) -> Possible<V> {
+        self.update(value);
+        self.possible.last().cloned().unwrap_or_else(|| self.start())
+    }
+
+    fn explain(&mut self, value: Option<V>) -> Explanation<V> {
+        self.update(value);
+        explain(&self.levels, self.init, self.folded_until, self.policy.as_ref(), self.retractions.all())
+    }
+
+    fn value_at(&mut self, value: Option<V>, moment: Moment) -> ValueAt<V> {
+        self.update(value);
+        if self.folded_until.is_some_and(|w| moment < w) {
+            return ValueAt::Folded;
+        }
+        // Start from the cache after the levels wholly before moment.
+        let before = self.reach.partition_point(|end| *end < moment);
+        let (init, possible) = match before {
+            0 => (self.init, self.start()),
+            _ => (self.cumulative[before - 1], self.possible[before - 1].clone())
+        };
+        value_at(&self.levels[before..], init, possible, self.policy.as_ref(), moment)
+    }
+
+    fn advance(&mut self, source: SourceKind, horizon: Moment) -> Result<(), Error> {
+        let rebound = self.constraints.horizon(&source, horizon);
+        self.reposition(rebound);
+        self.watermark.advance(source, horizon);
+        Ok(())
+    }
+
+    fn expect(&mut self, source: SourceKind) {
+        self.watermark.expect(source);
+    }
+
+    fn deviation(&mut self, source: SourceKind, deviation: Deviation) -> Result<(), Error> {
+        let retimed = self.constraints.retime(source, deviation);
+        self.reposition(retimed);
+        Ok(())
+    }
+
+    fn snapshot(&mut self, value: Option<V>) -> Option<Snapshot<V>> {
+        self.update(value);
+        Some(Snapshot {
+            value: self.init,
+            possible: self.snapshot.clone(),
+            folded_until: Some(self.folded_until?),
+            pending: pending(self.levels.iter(), &self.constraints, &self.retractions)
+        })
+    }
+
+    fn restore(&mut self, snapshot: Snapshot<V>) {
+        self.cumulative.clear();
+        self.possible.clear();
+        (self.init, self.snapshot, self.folded_until) = (snapshot.value, snapshot.possible, None);
+        readmit(self, snapshot.pending);
+        self.folded_until = snapshot.folded_until;
+    }
+
+    fn duplicates(&self) -> &HashMap<SourceKind, u64> {
+        self.seen.dropped()
+    }
+}
+
+
+// Interval-tree backend - each region is a maximal run of overlapping observations,
+// holding the levels (in execution order) those observations resolve into.
+pub struct History<V = Value> {
+    history: NoditMap<Moment, Interval, Vec<Level<V>>>,
+    init: Option<V>, // Snapshot of regions folded away - None if nothing folded yet.
+    snapshot: Possible<V>,
+    watermark: Watermark,
+    folded_until: Option<Moment>,
+    seen: Seen,
+    retractions: Retractions<V>,
+    constraints: Constraints,
+    policy: Box<dyn ConflictPolicy<V>>,
+}
+
+impl<V: Quantity> History<V> {
+    pub fn new(policy: Box<dyn ConflictPolicy<V>>) -> History<V> {
+        History { history: NoditMap::new(), init: None, snapshot: Possible::Unknown, watermark: Watermark::new(), folded_until: None, seen: Seen::new(), retractions: Retractions::new(), constraints: Constraints::new(), policy }
+    }
+
+    fn replay(&mut self, init: Option<V>) -> (Option<V>, Possible<V>) {
+        let (mut cumulative, mut possible) = match self.folded_until {
+            Some(_) => (self.init, self.snapshot.clone()),
+            None => (init, init.into())
+        };
+        let watermark = self.watermark.get();
+        let mut reach = None; // Latest end of any level so far.
+        let mut stable = None;
+
+        for (region, levels) in self.history.iter() {
+            for (i, level) in levels.iter().enumerate() {
+                cumulative = level.resolve(cumulative, self.policy.as_ref());
+                possible = level.possible(&possible);
+                reach = max(reach, Some(level.interval.1));
+                if watermark.is_some_and(|w| reach < Some(w)) {
+                    stable = Some((*region, i + 1, cumulative, possible.clone())); // Levels so far can never be reordered.
+                }
+            }
+        }
+
+        // Fold every stable level into the snapshot - regions are disjoint, so this removes only theirs.
+        // The last of them may share its region with levels that are not - only the stable ones leave it.
+        let folded = stable.is_some();
+        if let Some((last, count, value, values)) = stable {
+            let mut folded: Vec<Interval> = self.history.iter().map(|(region, _)| *region).take_while(|region| *region != last).collect();
+            let mut levels: Vec<Level<V>> = match self.history.overlapping_mut(last).next() {
+                Some((_, levels)) if count < levels.len() => levels.drain(..count).collect(),
+                _ => { folded.push(last); vec![] }
+            };
+            for region in folded {
+                levels.extend(self.history.remove_overlapping(region).flat_map(|(_, levels)| levels));
+            }
+            for level in &levels {
+                self.seen.forget(level.observations.iter().map(|o| &o.id));
+                self.constraints.forget(level.observations.iter().map(|o| &o.id));
+                self.retractions.folded(&level.observations, watermark.unwrap());
+            }
+            self.init = value;
+            self.snapshot = values;
+        } else if watermark.is_some() && self.folded_until.is_none() {
+            (self.init, self.snapshot) = (init, init.into()); // Nothing stable yet - the fold starts at init.
+        }
+        self.folded_until = max(self.folded_until, watermark); // A restored fold may lie past what sources report.
+        if folded {
+            self.retractions.prune(self.folded_until.unwrap());
         }
 
-        return None; // Otherwise, no definition could be found!
+        return (cumulative, possible);
+    }
+
+    // Places an observation already validated against the regions it overlaps.
+    fn insert(&mut self, observation: Observation<V>) -> Result<(), InferenceError> {
+        let mut interval = observation.interval;
+        let mut levels = Vec::new();
+
+        for (region, region_levels) in self.history.remove_overlapping(observation.interval) { // any existing regions overlapping with new obs:
+            interval = interval.hull(&region); // Merged into one region (no longer separable by interval)
+            levels.extend(region_levels); // Regions are disjoint and in order - so are their levels.
+        }
+
+        let len = levels.len();
+        place(&mut levels, 0, len, observation);
+        // Every region it overlapped was just removed - one still there means the tree has lost track of it.
+        self.history.insert_strict(interval, levels).map_err(|_| InferenceError::RegionOverlap(interval))
+    }
+
+    // Removes a placed observation - the rest of its region may split apart, so each is placed again.
+    fn take(&mut self, id: &ObservationId) -> Result<Option<Observation<V>>, InferenceError> {
+        let Some(region) = self.history.iter()
+            .find(|(_, levels)| levels.iter().any(|l| l.observations.iter().any(|o| o.id == *id)))
+            .map(|(region, _)| *region) else { return Ok(None) };
+        let mut observation = None;
+        let levels: Vec<Level<V>> = self.history.remove_overlapping(region).flat_map(|(_, levels)| levels).collect();
+        for remaining in levels.into_iter().flat_map(|level| level.observations) {
+            if remaining.id == *id {
+                observation = Some(remaining);
+            } else {
+                self.insert(remaining)?;
+            }
+        }
+        Ok(observation)
+    }
+
+    // Places again observations whose timing changed - those folded away are left.
+    fn reposition(&mut self, ids: Vec<ObservationId>) -> Result<(), InferenceError> {
+        for id in ids {
+            if let Some(observation) = self.take(&id)? {
+                self.insert(timed(observation, &self.constraints))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_execution(&self) -> Vec<Level<V>> {
+        self.history.iter().flat_map(|(_, levels)| levels.iter().cloned()).collect()
     }
 }
 
+impl<V: Quantity> Inference<V> for History<V> {
+    fn add_new(&mut self, observation: Observation<V>) -> Result<Outcome, Error> {
+        if self.seen.repeated(&observation) {
+            return Ok(Outcome::Duplicate);
+        }
+        if let Some(folded_until) = self.folded_until.filter(|w| observation.interval.0 < *w) {
+            return Err(InferenceError::BehindWatermark { id: observation.id, folded_until }.into());
+        }
+        if self.retractions.withdraws(&observation.id) {
+            return Ok(if self.retractions.arrived(observation) { Outcome::Accepted } else { Outcome::Duplicate });
+        }
+        validate(&observation, &self.constraints)?;
+        self.seen.accept(observation.id.clone());
+        let narrowed = self.constraints.add(&observation);
+        self.insert(timed(observation, &self.constraints))?;
+        self.reposition(narrowed)?;
+        Ok(Outcome::Accepted)
+    }
+
+    fn retract(&mut self, retraction: Retraction) -> Result<Outcome, Error> {
+        if self.retractions.withdraws(&retraction.retracts) {
+            return Ok(Outcome::Duplicate); // Retracted already.
+        }
+        match self.take(&retraction.retracts)? {
+            Some(observation) => {
+                let changed = self.constraints.remove(&retraction.retracts);
+                self.reposition(changed)?;
+                self.retractions.record(retraction, Some(observation), self.folded_until);
+            }
+            None => {
+                // Folded already - undone just past the fold, if it can be. Otherwise not arrived yet.
+                if let Some(compensation) = self.folded_until.and_then(|w| self.retractions.compensation(&retraction, w)) {
+                    self.add_new(compensation?)?;
+                }
+                self.retractions.record(retraction, None, self.folded_until);
+            }
+        }
+        Ok(Outcome::Accepted)
+    }
+
+    fn apply(&mut self, init: Option<V>) -> Option<V> {
+        self.replay(init).0
+    }
+
+    fn possible(&mut self, init: Option<V>) -> Possible<V> {
+        self.replay(init).1
+    }
 
+    fn explain(&mut self, init: Option<V>) -> Explanation<V> {
+        self.replay(init); // Fold anything stable first.
+        let init = if self.folded_until.is_some() { self.init } else { init };
+        let levels = self.history.iter().flat_map(|(_, levels)| levels.iter());
+        explain(levels, init, self.folded_until, self.policy.as_ref(), self.retractions.all())
+    }
 
+    fn value_at(&mut self, init: Option<V>, moment: Moment) -> ValueAt<V> {
+        self.replay(init); // Fold anything stable first.
+        if self.folded_until.is_some_and(|w| moment < w) {
+            return ValueAt::Folded;
+        }
+        let (init, possible) = match self.folded_until {
+            Some(_) => (self.init, self.snapshot.clone()),
+            None => (init, init.into())
+        };
+        let levels = self.history.iter().flat_map(|(_, levels)| levels.iter());
+        value_at(levels, init, possible, self.policy.as_ref(), moment)
+    }
 
+    fn advance(&mut self, source: SourceKind, horizon: Moment) -> Result<(), Error> {
+        let rebound = self.constraints.horizon(&source, horizon);
+        self.reposition(rebound)?;
+        self.watermark.advance(source, horizon);
+        Ok(())
+    }
 
-//
-//
-// pub struct History {
-//     history: NoditMap<Moment, Interval, Level>
-// }
-//
-// #[derive(Debug)]
-// pub struct Level {
-//     interval: Interval,
-//     observations: Vec<Observation>
-// }
-//
-//
-// impl Level {
-//     fn new(first: Observation) -> Self {
-//         Level {
-//             interval: first.interval,
-//             observations: Vec::from([first])
-//         }
-//     }
-//
-//     // fn add_new(&mut self, observation: Observation) {
-//     //     self.interval = MERGE(self.interval, observation.interval);
-//     //     self.observations.push(observation);
-//     // }
-//
-//     fn merge(&mut self, other: Level) {
-//         self.observations.extend(other.observations); // When levels merge merge observations
-//         self.interval = MERGE(self.interval, other.interval); // AND grow interval
-//     }
-//
-//     fn definition(&self) -> Option<DefinitionPredicate> {
-//
-//     }
-// }
-//
-// impl History {
-//     pub(crate) fn new() -> History {
-//         History { history: NoditMap::new() }
-//     }
-//
-//     pub(crate) fn add_new(&mut self, observation: Observation) {
-//         // Add new observation to history
-//         let interval = observation.interval;
-//         let mut new_level = Level::new(observation); // Instantiate new level set for observation.
-//
-//         for (_, level) in self.history.remove_overlapping(interval) { // any existing level-sets overlapping with new obs:
-//             new_level.merge(level); // Merged into one new level set (they can no longer be ordered)
-//         }
-//         self.history.insert_strict(new_level.interval, new_level).unwrap() // Expect to succeed - Above remove_overlap guarantees it.
-//     }
-//
-//     pub fn apply(&mut self, init: Option<Value>) -> Option<Value> {
-//         let mut cumulative = init;
-//
-//         for (_, level) in self.history.iter() {
-//             // info!("Processing Level: {:?}", level);
-//
-//
-//             match level.definition() {
-//                 Some(definition) => match definition {
-//                     DefinitionPredicate::Transition { v_0, v_1 } => {
-//                         if cumulative.is_some() && cumulative == Some(v_0) {
-//                             cumulative = Some(v_1);
-//                         } else {
-//                             cumulative = None;
-//                             info!("Inference - Conflict : {level:?}")
-//                         }
-//                     }
-//                     DefinitionPredicate::Mutation { delta } => {
-//                         if let Some(v) = cumulative {
-//                             cumulative = Some(v + delta);
-//                         }
-//                     }
-//                     DefinitionPredicate::Assignment { v_new } => {
-//                         cumulative = Some(v_new);
-//                     }
-//                 }
-//                 None => {
-//                     info!("Inference - Undefined Level: {level:?}");
-//                     cumulative = None
-//                 },
-//             }
-//         }
-//
-//         return cumulative;
-//     }
-// }
\ No newline at end of file
+    fn expect(&mut self, source: SourceKind) {
+        self.watermark.expect(source);
+    }
+
+    fn deviation(&mut self, source: SourceKind, deviation: Deviation) -> Result<(), Error> {
+        let retimed = self.constraints.retime(source, deviation);
+        Ok(self.reposition(retimed)?)
+    }
+
+    fn snapshot(&mut self, init: Option<V>) -> Option<Snapshot<V>> {
+        self.replay(init);
+        Some(Snapshot {
+            value: self.init,
+            possible: self.snapshot.clone(),
+            folded_until: Some(self.folded_until?),
+            pending: pending(self.history.iter().flat_map(|(_, levels)| levels.iter()), &self.constraints, &self.retractions)
+        })
+    }
+
+    fn restore(&mut self, snapshot: Snapshot<V>) {
+        (self.init, self.snapshot, self.folded_until) = (snapshot.value, snapshot.possible, None);
+        readmit(self, snapshot.pending);
+        self.folded_until = snapshot.folded_until;
+    }
+
+    fn duplicates(&self) -> &HashMap<SourceKind, u64> {
+        self.seen.dropped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use crate::inference::explain::{Conflict, Resolution};
+    use crate::inference::interval::{Deviation, Interval, Moment, TimeDistribution};
+    use crate::inference::policy::{LastEndWins, Strict};
+    use crate::inference::possible::Possible;
+    use crate::inference::query::ValueAt;
+    use crate::error::{Error, InferenceError};
+    use crate::inference::{Inference, Outcome};
+    use crate::observations::{DefinitionPredicate, Observation, ObservationId, Retraction, SourceKind};
+    use rand::Rng;
+    use super::{place, History, Level, NewHistory};
+
+    fn observation(n: u64, definition: DefinitionPredicate, start: u64, end: u64) -> Observation {
+        let source = SourceKind::Record("Recorder".to_string());
+        Observation {
+            id: ObservationId::Sequence(source.clone(), n),
+            definition,
+            interval: Interval(Moment(start), Moment(end)),
+            source,
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: None,
+            timestamp: None,
+        }
+    }
+
+    fn level(observations: Vec<Observation>) -> Level {
+        let mut observations = observations.into_iter();
+        let mut level = Level::new(observations.next().unwrap());
+        observations.for_each(|o| level.merge(Level::new(o)));
+        level
+    }
+
+    fn chain() -> Level {
+        level(vec![
+            observation(0, DefinitionPredicate::Transition { v_0: 9, v_1: 8 }, 0, 10),
+            observation(1, DefinitionPredicate::Transition { v_0: 10, v_1: 9 }, 5, 15),
+        ])
+    }
+
+    #[test]
+    fn unknown_is_not_a_conflict() {
+        assert_eq!(chain().possible(&Possible::Unknown), Possible::Unknown);
+        assert_eq!(chain().possible(&Possible::Known(BTreeSet::from([10]))), Possible::Known(BTreeSet::from([8])));
+        // No order of the chain starts from 5 - nothing is reachable, which is not the same as unknown.
+        assert_eq!(chain().possible(&Possible::Known(BTreeSet::from([5]))), Possible::Known(BTreeSet::new()));
+    }
+
+    #[test]
+    fn unknown_orders_go_to_the_policy() {
+        let sale = level(vec![
+            observation(0, DefinitionPredicate::Transition { v_0: 9, v_1: 8 }, 0, 10),
+            observation(1, DefinitionPredicate::Mutation { delta: -1 }, 5, 15),
+        ]);
+        let (value, resolution) = sale.resolution(None, &LastEndWins);
+        assert_eq!(value, Some(7)); // Seen at 8, then sold one.
+        assert!(matches!(resolution, Resolution::Conflict { conflict: Conflict::Unknown, .. }));
+        let (value, resolution) = sale.resolution(Some(9), &Strict);
+        assert_eq!(value, Some(7));
+        assert!(matches!(resolution, Resolution::Ordered));
+    }
+
+    #[test]
+    fn transitions_chain_in_the_order_they_allow() {
+        assert!(matches!(chain().resolution(Some(10), &Strict), (Some(8), Resolution::Ordered)));
+        assert_eq!(chain().resolution(Some(9), &Strict).0, None); // 10 to 9 can then never apply.
+
+        // One poller saw 9 to 8, and later 10 to 9 - no order reaches 8 from 10, whatever else overlaps them.
+        let reversed = level(vec![
+            observation(0, DefinitionPredicate::Transition { v_0: 9, v_1: 8 }, 0, 5),
+            observation(1, DefinitionPredicate::Transition { v_0: 10, v_1: 9 }, 6, 15),
+            observation(2, DefinitionPredicate::Mutation { delta: 0 }, 0, 15),
+        ]);
+        let (value, resolution) = reversed.resolution(Some(10), &Strict);
+        assert_eq!(value, None);
+        assert!(matches!(resolution, Resolution::Conflict { conflict: Conflict::Impossible, .. }));
+
+        // A sale between a poll's transitions still chains - mutations take part in the path.
+        let sold = level(vec![
+            observation(0, DefinitionPredicate::Transition { v_0: 10, v_1: 9 }, 0, 5),
+            observation(1, DefinitionPredicate::Mutation { delta: -1 }, 0, 15),
+            observation(2, DefinitionPredicate::Transition { v_0: 8, v_1: 7 }, 6, 15),
+        ]);
+        assert!(matches!(sold.resolution(Some(10), &Strict), (Some(7), Resolution::Ordered)));
+    }
+
+    #[test]
+    fn nothing_reachable_stays_so() {
+        let assignment = level(vec![observation(0, DefinitionPredicate::Assignment { v_new: 3 }, 0, 10)]);
+        assert_eq!(assignment.possible(&Possible::Known(BTreeSet::new())), Possible::Known(BTreeSet::new()));
+        assert_eq!(assignment.possible(&Possible::Unknown), Possible::Known(BTreeSet::from([3])));
+    }
+
+    #[test]
+    fn too_large_until_assigned() {
+        let mut observations: Vec<Observation> = (0..13).map(|n| observation(n, DefinitionPredicate::Mutation { delta: -1 }, n, 20)).collect();
+        observations.push(observation(13, DefinitionPredicate::Transition { v_0: 10, v_1: 9 }, 0, 20));
+        let large = level(observations);
+        assert_eq!(large.possible(&Possible::Known(BTreeSet::from([10]))), Possible::TooLarge);
+
+        let mutation = level(vec![observation(0, DefinitionPredicate::Mutation { delta: 1 }, 30, 40)]);
+        assert_eq!(mutation.possible(&Possible::TooLarge), Possible::TooLarge);
+        let assignment = level(vec![observation(0, DefinitionPredicate::Assignment { v_new: 3 }, 30, 40)]);
+        assert_eq!(assignment.possible(&Possible::TooLarge), Possible::Known(BTreeSet::from([3])));
+    }
+
+    #[test]
+    fn empty_levels_are_an_error() {
+        assert!(matches!(NewHistory::<i64>::definition(&vec![]), Err(InferenceError::EmptyLevel)));
+        let single = vec![observation(0, DefinitionPredicate::Mutation { delta: -1 }, 0, 10)];
+        assert!(matches!(NewHistory::definition(&single), Ok(Some(DefinitionPredicate::Mutation { delta: -1 }))));
+    }
+
+    // Repeats are dropped as duplicates until folded - then forgotten, and rejected as behind the fold.
+    fn forgets_once_folded(mut history: impl Inference) {
+        let sale = observation(0, DefinitionPredicate::Mutation { delta: -1 }, 0, 10);
+        assert_eq!(history.add_new(sale.clone()).unwrap(), Outcome::Accepted);
+        assert_eq!(history.add_new(sale.clone()).unwrap(), Outcome::Duplicate);
+        assert_eq!(history.apply(Some(10)), Some(9));
+
+        history.advance(SourceKind::Record("Recorder".to_string()), Moment(20)).unwrap();
+        assert_eq!(history.apply(Some(10)), Some(9));
+        assert!(matches!(history.add_new(sale), Err(Error::Inference(InferenceError::BehindWatermark { .. }))));
+        assert_eq!(history.apply(Some(10)), Some(9));
+        assert_eq!(history.duplicates().values().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn duplicates_until_folded() {
+        forgets_once_folded(NewHistory::new(Box::new(Strict)));
+        forgets_once_folded(History::new(Box::new(Strict)));
+    }
+
+    #[test]
+    fn overflow_is_a_conflict() {
+        let large = level(vec![observation(0, DefinitionPredicate::Mutation { delta: i64::MAX }, 0, 10)]);
+        assert!(matches!(large.resolution(Some(1), &Strict), (None, Resolution::Conflict { conflict: Conflict::Overflow, .. })));
+        assert_eq!(large.possible(&Possible::Known(BTreeSet::from([-1, 1]))), Possible::Known(BTreeSet::from([i64::MAX - 1])));
+
+        // Summed alone the deltas overflow - applied in either order to the least value, they never do.
+        let both = level(vec![
+            observation(0, DefinitionPredicate::Mutation { delta: i64::MAX }, 0, 10),
+            observation(1, DefinitionPredicate::Mutation { delta: i64::MAX }, 0, 10),
+        ]);
+        assert!(matches!(both.resolution(Some(i64::MIN), &Strict), (Some(v), Resolution::Ordered) if v == i64::MAX - 1));
+    }
+
+    // Touching intervals share a moment - either change could have come first, so they share a level.
+    #[test]
+    fn touching_observations_share_a_level() {
+        let mut history = NewHistory::new(Box::new(Strict));
+        history.add_new(observation(0, DefinitionPredicate::Mutation { delta: -1 }, 0, 10)).unwrap();
+        history.add_new(observation(1, DefinitionPredicate::Assignment { v_new: 5 }, 10, 20)).unwrap();
+        assert_eq!(history.get_execution().len(), 1);
+
+        history.add_new(observation(2, DefinitionPredicate::Mutation { delta: -1 }, 21, 30)).unwrap();
+        assert_eq!(history.get_execution().len(), 2);
+    }
+
+    // Levels of more than one observation - those left to the policy to order - and how many observations they hold.
+    fn unordered(levels: &[Level]) -> (usize, usize) {
+        levels.iter().filter(|l| l.observations.len() > 1).fold((0, 0), |(count, held), l| (count + 1, held + l.observations.len()))
+    }
+
+    // A platform's sales, seen both by its polls - sent every 10 ticks, each reading its value a tick later
+    // and replied to 3 ticks after sending - and by fetching its records, stamped when each sale happened.
+    fn polled_and_recorded(sales: usize, rng: &mut impl Rng) -> Vec<Observation> {
+        let (polling, recording) = (SourceKind::Polling("Square".to_string()), SourceKind::Record("Square".to_string()));
+        let mut observations = Vec::new();
+        let mut at = 0;
+        for sale in 0..sales as u64 {
+            at += rng.random_range(20..80);
+            observations.push(Observation {
+                id: ObservationId::Change(recording.clone(), format!("{sale}")),
+                interval: Interval(Moment(at), Moment(at)),
+                source: recording.clone(),
+                timestamp: Some(Moment(at)),
+                reported: Some(Moment(at + 100)),
+                ..observation(sale, DefinitionPredicate::Mutation { delta: -1 }, at, at)
+            });
+            // Read by the first poll sent at least a tick before it - its window opens at the poll before.
+            let sent = (at / 10 + 1) * 10;
+            observations.push(Observation {
+                id: ObservationId::Sequence(polling.clone(), sale),
+                interval: Interval(Moment(sent - 10), Moment(sent + 3)),
+                source: polling.clone(),
+                reported: Some(Moment(sent + 3)),
+                ..observation(sale, DefinitionPredicate::Mutation { delta: -1 }, sent - 10, sent + 3)
+            });
+        }
+        observations
+    }
+
+    // Measured against the same observations at their reported intervals - capped by their replies, but never narrowed.
+    // Records alone in a poll are bounded by it, so fewer sales run together.
+    #[test]
+    fn propagation_shrinks_unordered_levels() {
+        let mut rng = rand::rng();
+        let (mut reported, mut narrowed) = ((0, 0), (0, 0));
+        for _ in 0..50 {
+            let mut history = NewHistory::new(Box::new(Strict));
+            history.deviation(SourceKind::Record("Square".to_string()), Deviation { min: -20, max: 20 }).unwrap(); // A clock known only roughly.
+            history.advance(SourceKind::Polling("Square".to_string()), Moment(0)).unwrap(); // Holds the watermark back - nothing folds.
+            for observation in polled_and_recorded(30, &mut rng) {
+                history.add_new(observation).unwrap();
+            }
+            history.advance(SourceKind::Record("Square".to_string()), Moment(u64::MAX)).unwrap(); // Every record has arrived.
+            history.apply(Some(100));
+
+            let mut levels = Vec::new();
+            for observation in history.levels.iter().flat_map(|l| l.observations.iter()) {
+                let interval = history.constraints.observed(&observation.id).unwrap();
+                let end = levels.len();
+                place(&mut levels, 0, end, Observation { interval, ..observation.clone() });
+            }
+            let (r, n) = (unordered(&levels), unordered(&history.levels));
+            (reported, narrowed) = ((reported.0 + r.0, reported.1 + r.1), (narrowed.0 + n.0, narrowed.1 + n.1));
+        }
+        // A sale's poll and record are never ordered against one another - the smallest unordered level is the pair.
+        let (mean_reported, mean_narrowed) = (reported.1 as f64 / reported.0 as f64, narrowed.1 as f64 / narrowed.0 as f64);
+        assert!(mean_narrowed < mean_reported - 0.5, "Unordered levels hold {mean_narrowed} observations once narrowed, against {mean_reported} as reported");
+    }
+
+    // Exact between levels, a range part way through one, folded behind the fold - and unknown past what can be enumerated.
+    fn values_at(mut history: impl Inference) {
+        history.add_new(observation(0, DefinitionPredicate::Assignment { v_new: 10 }, 0, 10)).unwrap();
+        history.add_new(observation(1, DefinitionPredicate::Mutation { delta: -1 }, 20, 30)).unwrap();
+        history.add_new(observation(2, DefinitionPredicate::Mutation { delta: -2 }, 25, 35)).unwrap();
+        assert_eq!(history.value_at(None, Moment(15)), ValueAt::Exact(Some(10)));
+        assert_eq!(history.value_at(None, Moment(27)), ValueAt::Range { low: 7, high: 10 });
+        assert_eq!(history.value_at(None, Moment(32)), ValueAt::Range { low: 7, high: 9 }); // The first sale had happened.
+        assert_eq!(history.value_at(None, Moment(40)), ValueAt::Exact(Some(7)));
+
+        // More at once than fit a bitset of them.
+        for n in 3..73 {
+            history.add_new(observation(n, DefinitionPredicate::Mutation { delta: -1 }, 100, 120)).unwrap();
+        }
+        assert_eq!(history.value_at(None, Moment(110)), ValueAt::Exact(None));
+
+        history.advance(SourceKind::Record("Recorder".to_string()), Moment(50)).unwrap();
+        assert_eq!(history.value_at(None, Moment(15)), ValueAt::Folded);
+        assert_eq!(history.value_at(None, Moment(60)), ValueAt::Exact(Some(7)));
+    }
+
+    #[test]
+    fn value_at_past_moments() {
+        values_at(NewHistory::new(Box::new(Strict)));
+        values_at(History::new(Box::new(Strict)));
+    }
+
+    // A source yet to report holds the fold back - though another's horizon has passed the sale.
+    fn held_for(mut history: impl Inference) {
+        let poller = SourceKind::Polling("Poller".to_string());
+        history.expect(poller.clone());
+        history.add_new(observation(0, DefinitionPredicate::Mutation { delta: -1 }, 0, 10)).unwrap();
+        history.advance(SourceKind::Record("Recorder".to_string()), Moment(20)).unwrap();
+        assert!(history.snapshot(Some(10)).is_none());
+
+        history.advance(poller, Moment(30)).unwrap();
+        let snapshot = history.snapshot(Some(10)).unwrap();
+        assert_eq!((snapshot.value, snapshot.folded_until), (Some(9), Some(Moment(20))));
+    }
+
+    #[test]
+    fn expected_sources_hold_the_watermark() {
+        held_for(NewHistory::new(Box::new(Strict)));
+        held_for(History::new(Box::new(Strict)));
+    }
+
+    fn retraction(n: u64, retracts: &Observation) -> Retraction {
+        let source = SourceKind::Record("Recorder".to_string());
+        Retraction { id: ObservationId::Sequence(source.clone(), 100 + n), retracts: retracts.id.clone(), source, reason: "Voided".to_string() }
+    }
+
+    // Withdrawn while held, or once folded by its inverse - either way shown alongside what it withdrew.
+    fn retracts(mut history: impl Inference) {
+        let recorder = SourceKind::Record("Recorder".to_string());
+        let (sale, recount) = (
+            observation(0, DefinitionPredicate::Mutation { delta: -1 }, 0, 10),
+            observation(1, DefinitionPredicate::Assignment { v_new: 5 }, 20, 30)
+        );
+        history.add_new(sale.clone()).unwrap();
+        assert_eq!(history.retract(retraction(0, &sale)).unwrap(), Outcome::Accepted);
+        assert_eq!(history.apply(Some(10)), Some(10));
+        let explanation = history.explain(Some(10));
+        assert!(explanation.levels.is_empty());
+        assert!(matches!(&explanation.retracted[..], [withdrawn] if withdrawn.observation.as_ref().is_some_and(|o| o.id == sale.id)));
+
+        let later = observation(2, DefinitionPredicate::Mutation { delta: -2 }, 40, 50);
+        history.add_new(later.clone()).unwrap();
+        history.add_new(recount.clone()).unwrap();
+        history.advance(recorder.clone(), Moment(60)).unwrap();
+        assert_eq!(history.apply(Some(10)), Some(3));
+        assert!(matches!(history.add_new(later.clone()), Err(Error::Inference(InferenceError::BehindWatermark { .. }))));
+
+        // Folded since - the sale is undone just past the fold, the recount cannot be.
+        assert_eq!(history.retract(retraction(1, &later)).unwrap(), Outcome::Accepted);
+        assert_eq!(history.apply(Some(10)), Some(5));
+        let explanation = history.explain(Some(10));
+        assert!(matches!(&explanation.levels[..], [level] if level.observations[0].id == retraction(1, &later).id && level.after == Some(5)));
+        assert!(explanation.retracted.iter().any(|r| r.retraction.retracts == later.id));
+        assert!(matches!(history.retract(retraction(2, &recount)), Err(Error::Inference(InferenceError::Irreversible { .. }))));
+
+        // Folded well past - what was withdrawn could only arrive behind the fold, and what waits has waited long enough.
+        let never = observation(3, DefinitionPredicate::Mutation { delta: -1 }, 100, 110);
+        history.retract(retraction(3, &never)).unwrap();
+        history.advance(recorder, Moment(10_000_000)).unwrap();
+        history.apply(Some(10));
+        assert!(history.explain(Some(10)).retracted.is_empty());
+    }
+
+    #[test]
+    fn retractions_restore_the_value() {
+        retracts(NewHistory::new(Box::new(Strict)));
+        retracts(History::new(Box::new(Strict)));
+    }
+}
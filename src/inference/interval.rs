@@ -1,35 +1,222 @@
 use std::cmp::{max, min};
 use nodit::{DiscreteFinite, InclusiveInterval};
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+use crate::error::InferenceError;
 use crate::observations::Tick;
 
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Moment(pub Tick);
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Interval(pub Moment, pub Moment);
 
-pub const MERGE: fn(Interval, Interval) -> Interval = |a, b| Interval(min(a.0, b.0), max(a.1, b.1));
-pub const LT: fn(Interval, Interval) -> bool = |a, b| (a.1 < b.0);
-pub const GT: fn(Interval, Interval) -> bool = |a,b| (a.0 > b.1);
+// Where in its interval an observed change is thought to have happened.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum TimeDistribution {
+    #[default]
+    Uniform, // Anywhere - all a poll can say.
+    Normal { mean: f64, std_dev: f64 }, // Truncated to the interval - a timestamp with estimated clock deviation.
+}
+
+// Tries before a normal sample falling outside the interval is clamped into it instead.
+const TRUNCATION_TRIES: usize = 16;
+
+impl TimeDistribution {
+    pub fn sample(&self, interval: Interval, rng: &mut impl Rng) -> f64 {
+        let (start, end) = (interval.0.0 as f64, interval.1.0 as f64);
+        if start >= end {
+            return start;
+        }
+        match self {
+            TimeDistribution::Uniform => rng.random_range(start..=end),
+            TimeDistribution::Normal { mean, std_dev } => {
+                let Ok(normal) = Normal::new(*mean, *std_dev) else {
+                    return mean.clamp(start, end); // Degenerate - no spread to sample.
+                };
+                (0..TRUNCATION_TRIES).map(|_| normal.sample(rng))
+                    .find(|t| (start..=end).contains(t))
+                    .unwrap_or_else(|| mean.clamp(start, end))
+            }
+        }
+    }
+}
+
+// Bounds on how far a source's clock runs ahead of ours - negative if behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deviation {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Deviation {
+    // When a change the source stamped at timestamp could have happened by our clock.
+    pub fn interval(&self, timestamp: Moment) -> Interval {
+        Interval(timestamp.saturating_offset(self.max.saturating_neg()), timestamp.saturating_offset(self.min.saturating_neg()))
+    }
+
+    // Deviation is thought likeliest midway between its bounds - and most likely within them.
+    pub fn distribution(&self, timestamp: Moment) -> TimeDistribution {
+        TimeDistribution::Normal {
+            mean: timestamp.0 as f64 - (self.max as f64 + self.min as f64) / 2.0,
+            std_dev: (self.max as f64 - self.min as f64) / 4.0,
+        }
+    }
+}
+
+impl Moment {
+    pub fn checked_add(self, ticks: Tick) -> Option<Moment> {
+        self.0.checked_add(ticks).map(Moment)
+    }
+
+    pub fn checked_sub(self, ticks: Tick) -> Option<Moment> {
+        self.0.checked_sub(ticks).map(Moment)
+    }
 
-pub const OVERLAP: fn(Interval, Interval) -> bool = |a, b| (!LT(a, b) && !GT(a, b));
+    // Shifted by a signed offset - a clock deviation.
+    pub fn checked_offset(self, ticks: i64) -> Option<Moment> {
+        self.0.checked_add_signed(ticks).map(Moment)
+    }
+
+    pub fn saturating_offset(self, ticks: i64) -> Moment {
+        Moment(self.0.saturating_add_signed(ticks))
+    }
+}
+
+// How two intervals lie against each other - Allen's 13 relations, read as "a is <relation> b".
+// Intervals are inclusive, so meeting intervals share a moment and are not ordered.
+// Checked in the order listed - a point interval at the start of another Meets it, not Starts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Relation {
+    Equals,
+    Before, // a ends before b starts.
+    After,
+    Meets, // a ends as b starts.
+    MetBy,
+    Starts, // Same start, a ends first.
+    StartedBy,
+    Finishes, // Same end, a starts last.
+    FinishedBy,
+    During, // a strictly inside b.
+    Contains,
+    Overlaps, // a starts first, ends inside b.
+    OverlappedBy,
+}
+
+impl Relation {
+    pub fn inverse(self) -> Relation {
+        match self {
+            Relation::Equals => Relation::Equals,
+            Relation::Before => Relation::After,
+            Relation::After => Relation::Before,
+            Relation::Meets => Relation::MetBy,
+            Relation::MetBy => Relation::Meets,
+            Relation::Starts => Relation::StartedBy,
+            Relation::StartedBy => Relation::Starts,
+            Relation::Finishes => Relation::FinishedBy,
+            Relation::FinishedBy => Relation::Finishes,
+            Relation::During => Relation::Contains,
+            Relation::Contains => Relation::During,
+            Relation::Overlaps => Relation::OverlappedBy,
+            Relation::OverlappedBy => Relation::Overlaps,
+        }
+    }
+}
+
+impl Interval {
+    pub fn new(start: Moment, end: Moment) -> Result<Interval, InferenceError> {
+        let interval = Interval(start, end);
+        if !interval.is_valid() {
+            return Err(InferenceError::InvalidInterval(interval));
+        }
+        Ok(interval)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.0 <= self.1
+    }
+
+    // Ticks from start to end - 0 for a single moment.
+    pub fn width(&self) -> Tick {
+        self.1.0.saturating_sub(self.0.0)
+    }
+
+    pub fn relation(&self, other: &Interval) -> Relation {
+        let (a, b) = (self, other);
+        if a.0 == b.0 && a.1 == b.1 {
+            Relation::Equals
+        } else if a.1 < b.0 {
+            Relation::Before
+        } else if a.0 > b.1 {
+            Relation::After
+        } else if a.1 == b.0 {
+            Relation::Meets
+        } else if a.0 == b.1 {
+            Relation::MetBy
+        } else if a.0 == b.0 {
+            if a.1 < b.1 { Relation::Starts } else { Relation::StartedBy }
+        } else if a.1 == b.1 {
+            if a.0 > b.0 { Relation::Finishes } else { Relation::FinishedBy }
+        } else if a.0 > b.0 && a.1 < b.1 {
+            Relation::During
+        } else if a.0 < b.0 && a.1 > b.1 {
+            Relation::Contains
+        } else if a.0 < b.0 {
+            Relation::Overlaps
+        } else {
+            Relation::OverlappedBy
+        }
+    }
+
+    // Whether every moment of one comes before every moment of the other.
+    pub fn before(&self, other: &Interval) -> bool {
+        self.relation(other) == Relation::Before
+    }
+
+    pub fn after(&self, other: &Interval) -> bool {
+        self.relation(other) == Relation::After
+    }
+
+    // Whether they share any moment.
+    pub fn intersects(&self, other: &Interval) -> bool {
+        !self.before(other) && !self.after(other)
+    }
+
+    pub fn intersection(&self, other: &Interval) -> Option<Interval> {
+        self.intersects(other).then(|| Interval(max(self.0, other.0), min(self.1, other.1)))
+    }
+
+    // Smallest interval covering both.
+    pub fn hull(&self, other: &Interval) -> Interval {
+        Interval(min(self.0, other.0), max(self.1, other.1))
+    }
+
+    pub fn contains(&self, moment: Moment) -> bool {
+        self.0 <= moment && moment <= self.1
+    }
+
+    pub fn covers(&self, other: &Interval) -> bool {
+        self.0 <= other.0 && other.1 <= self.1
+    }
+}
 
 impl DiscreteFinite for Moment {
     const MIN: Self = Moment(0);
-    const MAX: Self = Moment(u64::max_value());
+    const MAX: Self = Moment(u64::MAX);
 
     fn up(self) -> Option<Self>
     where
         Self: Sized
     {
-        Some(Moment(self.0 + 1))
+        self.checked_add(1)
     }
 
     fn down(self) -> Option<Self>
     where
         Self: Sized
     {
-        Some(Moment(self.0 - 1))
+        self.checked_sub(1)
     }
 }
 impl From<nodit::Interval<Moment>> for Interval {
@@ -47,3 +234,74 @@ impl InclusiveInterval<Moment> for Interval {
         self.1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nodit::DiscreteFinite;
+    use super::{Interval, Moment, Relation};
+
+    fn interval(start: u64, end: u64) -> Interval {
+        Interval(Moment(start), Moment(end))
+    }
+
+    #[test]
+    fn each_relation() {
+        let b = interval(10, 20);
+        let relations = [
+            (interval(10, 20), Relation::Equals),
+            (interval(0, 9), Relation::Before),
+            (interval(21, 30), Relation::After),
+            (interval(0, 10), Relation::Meets),
+            (interval(20, 30), Relation::MetBy),
+            (interval(10, 15), Relation::Starts),
+            (interval(10, 25), Relation::StartedBy),
+            (interval(15, 20), Relation::Finishes),
+            (interval(5, 20), Relation::FinishedBy),
+            (interval(12, 18), Relation::During),
+            (interval(5, 25), Relation::Contains),
+            (interval(5, 15), Relation::Overlaps),
+            (interval(15, 25), Relation::OverlappedBy),
+        ];
+        for (a, relation) in relations {
+            assert_eq!(a.relation(&b), relation, "{a:?} against {b:?}");
+            assert_eq!(b.relation(&a), relation.inverse(), "{b:?} against {a:?}");
+        }
+    }
+
+    // Inclusive ends - intervals sharing only a moment touch, and are not ordered.
+    #[test]
+    fn touching_intervals_share_a_moment() {
+        let (a, b) = (interval(0, 10), interval(10, 20));
+        assert!(!a.before(&b) && !b.after(&a));
+        assert!(a.intersects(&b));
+        assert_eq!(a.intersection(&b), Some(interval(10, 10)));
+        assert_eq!(a.hull(&b), interval(0, 20));
+
+        let (c, d) = (interval(0, 9), interval(10, 20));
+        assert!(c.before(&d) && d.after(&c));
+        assert_eq!(c.intersection(&d), None);
+    }
+
+    // A single moment at either end of another meets it - inside it, it is during it.
+    #[test]
+    fn point_intervals() {
+        let (point, b) = (interval(10, 10), interval(10, 20));
+        assert_eq!(point.relation(&b), Relation::Meets);
+        assert_eq!(interval(20, 20).relation(&b), Relation::MetBy);
+        assert_eq!(interval(15, 15).relation(&b), Relation::During);
+        assert_eq!(point.relation(&point), Relation::Equals);
+        assert_eq!(point.width(), 0);
+        assert!(b.contains(Moment(10)) && b.contains(Moment(20)) && !b.contains(Moment(21)));
+        assert!(b.covers(&point) && !point.covers(&b));
+    }
+
+    #[test]
+    fn bounds_are_checked() {
+        assert!(Interval::new(Moment(5), Moment(4)).is_err());
+        assert_eq!(Interval::new(Moment(5), Moment(5)).unwrap(), interval(5, 5));
+        assert_eq!(Moment(0).down(), None);
+        assert_eq!(Moment(u64::MAX).up(), None);
+        assert_eq!(Moment(5).checked_offset(-6), None);
+        assert_eq!(Moment(5).saturating_offset(-6), Moment(0));
+    }
+}
@@ -1,2 +1,94 @@
+use std::collections::HashMap;
+use log::error;
+use serde::{Deserialize, Serialize};
+use crate::error::Error;
+use crate::inference::explain::Explanation;
+use crate::inference::interval::{Deviation, Moment};
+use crate::inference::possible::Possible;
+use crate::inference::query::ValueAt;
+use crate::observations::{Observation, Report, Retraction, SourceKind};
+use crate::value::{Quantity, Value};
+
+pub mod dedup;
+pub mod explain;
 pub mod history;
-pub mod interval;
\ No newline at end of file
+pub mod interval;
+pub mod policy;
+pub mod possible;
+pub mod propagation;
+pub mod query;
+pub mod retraction;
+pub mod watermark;
+
+// What a history folded into, with what it held past the fold - a history can be dropped, and resumed from it.
+// A target not yet folded starts from its initial value alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "V: Ord + Deserialize<'de>"))]
+pub struct Snapshot<V = Value> {
+    pub value: Option<V>,
+    pub possible: Possible<V>,
+    pub folded_until: Option<Moment>, // Nothing starting before this can be added any more.
+    #[serde(default)]
+    pub pending: Vec<Report<V>>, // Readmitted on restore - clock estimates, observations not yet folded, retractions.
+}
+
+impl<V: Quantity> Snapshot<V> {
+    pub fn initial(value: Option<V>) -> Self {
+        Snapshot { value, possible: value.into(), folded_until: None, pending: Vec::new() }
+    }
+}
+
+// Readmits what a snapshot held past its fold - before the fold is restored, as they were accepted before it.
+pub(crate) fn readmit<V: Quantity>(history: &mut impl Inference<V>, pending: Vec<Report<V>>) {
+    for report in pending {
+        let readmitted = match report {
+            Report::Observed(observation) => history.add_new(observation).map(drop),
+            Report::Retracted(retraction) => history.retract(retraction).map(drop),
+            Report::Deviation(source, deviation) => history.deviation(source, deviation),
+            Report::Horizon(source, horizon) => history.advance(source, horizon),
+        };
+        if let Err(e) = readmitted {
+            error!("Inference - Snapshot held a rejected report: {e}");
+        }
+    }
+}
+
+// What became of an observation or retraction the history did not reject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    Duplicate, // Accepted before - dropped, leaving the history unchanged.
+}
+
+pub trait Inference<V: Quantity = Value> {
+    // Rejected observations leave the history unchanged - as do repeats of accepted ones.
+    fn add_new(&mut self, observation: Observation<V>) -> Result<Outcome, Error>;
+    // Withdraws an earlier observation as if it never happened - one yet to arrive is dropped when it does.
+    // Once folded, a mutation is undone by its inverse just past the fold - anything else folded cannot be.
+    fn retract(&mut self, retraction: Retraction) -> Result<Outcome, Error>;
+    fn apply(&mut self, init: Option<V>) -> Option<V>;
+    // Every value some order of each level could reach, carried forward - or why they cannot be told.
+    fn possible(&mut self, init: Option<V>) -> Possible<V>;
+    // Trace of every level behind the value apply would give - serializable for support staff.
+    fn explain(&mut self, init: Option<V>) -> Explanation<V>;
+    // Value at a past moment - a range if levels were part way through then.
+    fn value_at(&mut self, init: Option<V>, moment: Moment) -> ValueAt<V>;
+    // Source promises no further observations starting before horizon - lets stable history be folded.
+    // Once folded, observations starting before the fold are rejected - they could no longer be ordered.
+    // A record horizon can narrow what that platform's polls saw.
+    fn advance(&mut self, source: SourceKind, horizon: Moment) -> Result<(), Error>;
+    // Source will report horizons - nothing folds until it has.
+    fn expect(&mut self, source: SourceKind);
+    // Source's clock deviation was revised - observations it stamped are retimed by it.
+    fn deviation(&mut self, source: SourceKind, deviation: Deviation) -> Result<(), Error>;
+    // What the history folded into, with what it holds past the fold - None until anything is folded.
+    fn snapshot(&mut self, init: Option<V>) -> Option<Snapshot<V>>;
+    // Snapshot once every level is folded - None while any could still be reordered.
+    fn settled(&mut self, init: Option<V>) -> Option<Snapshot<V>> {
+        self.snapshot(init).filter(|snapshot| !snapshot.pending.iter().any(|report| matches!(report, Report::Observed(_))))
+    }
+    // Resumes from an earlier history's snapshot - before anything is added.
+    fn restore(&mut self, snapshot: Snapshot<V>);
+    // Repeated observations dropped so far, per source.
+    fn duplicates(&self) -> &HashMap<SourceKind, u64>;
+}
@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use crate::inference::history::Level;
+use crate::observations::{DefinitionPredicate, Observation, ObservationId, SourceKind};
+use crate::value::{Quantity, Value};
+
+// Decides a value for a level which could not be defined, or whose transition did not match.
+pub trait ConflictPolicy<V: Quantity = Value>: Debug + Send {
+    fn resolve(&self, level: &Level<V>, cumulative: Option<V>) -> Option<V>;
+
+    // Policies weighing how likely each order is report their resolution with its confidence.
+    fn weigh(&self, _level: &Level<V>, _cumulative: Option<V>) -> Option<Likely<V>> {
+        None
+    }
+}
+
+// Resolution of a level by the order it most likely happened in.
+#[derive(Debug, Clone, Serialize)]
+pub struct Likely<V = Value> {
+    pub value: Option<V>,
+    pub ordering: Vec<ObservationId>, // Likeliest order giving value.
+    pub confidence: f64, // Share of possible orders giving value.
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum ConflictPolicyConfig {
+    #[default]
+    Strict,
+    PessimisticMinimum,
+    PreferRecord,
+    PreferPlatform(String),
+    LastEndWins,
+    KeepPrevious,
+    MostLikely { samples: usize }
+}
+
+impl ConflictPolicyConfig {
+    pub fn build<V: Quantity>(&self) -> Box<dyn ConflictPolicy<V>> {
+        match self {
+            ConflictPolicyConfig::Strict => Box::new(Strict),
+            ConflictPolicyConfig::PessimisticMinimum => Box::new(PessimisticMinimum),
+            ConflictPolicyConfig::PreferRecord => Box::new(PreferRecord),
+            ConflictPolicyConfig::PreferPlatform(name) => Box::new(PreferPlatform(name.clone())),
+            ConflictPolicyConfig::LastEndWins => Box::new(LastEndWins),
+            ConflictPolicyConfig::KeepPrevious => Box::new(KeepPrevious),
+            ConflictPolicyConfig::MostLikely { samples } => Box::new(MostLikely { samples: *samples }),
+        }
+    }
+}
+
+// Replays observations in the given order - None if any transition does not match.
+fn replay<'a, V: Quantity>(observations: impl IntoIterator<Item = &'a Observation<V>>, cumulative: Option<V>) -> Option<V> {
+    let mut value = cumulative;
+    for observation in observations {
+        value = match observation.definition {
+            DefinitionPredicate::Assignment { v_new } => Some(v_new),
+            _ => observation.definition.apply(&value?)
+        };
+    }
+    return value;
+}
+
+// Resolves only the observations matching keep - as if the rest were never seen.
+// The subset must then resolve on its own, in the orders it allows - strictly, if it conflicts too.
+fn resolve_subset<V: Quantity>(level: &Level<V>, cumulative: Option<V>, keep: impl Fn(&Observation<V>) -> bool) -> Option<V> {
+    let observations: Vec<Observation<V>> = level.observations.iter().filter(|o| keep(o)).cloned().collect();
+    if observations.is_empty() {
+        return None;
+    }
+    Level { interval: level.interval, observations }.resolve(cumulative, &Strict)
+}
+
+// No resolution - the consensus becomes undefined.
+#[derive(Debug)]
+pub struct Strict;
+
+impl<V: Quantity> ConflictPolicy<V> for Strict {
+    fn resolve(&self, _level: &Level<V>, _cumulative: Option<V>) -> Option<V> {
+        None
+    }
+}
+
+// Lowest value any order of the level could produce - never overstates what is available.
+#[derive(Debug)]
+pub struct PessimisticMinimum;
+
+impl<V: Quantity> ConflictPolicy<V> for PessimisticMinimum {
+    fn resolve(&self, level: &Level<V>, cumulative: Option<V>) -> Option<V> {
+        match level.orders(&BTreeSet::from([cumulative])) {
+            Some(values) => values.first().cloned().flatten(), // Unknown if any order leaves it so.
+            // Too many orders to find the lowest - unknown, rather than risk overstating it.
+            None => None
+        }
+    }
+}
+
+// Trust timestamped records and pushes over polls, which only bound when a change happened.
+#[derive(Debug)]
+pub struct PreferRecord;
+
+impl<V: Quantity> ConflictPolicy<V> for PreferRecord {
+    fn resolve(&self, level: &Level<V>, cumulative: Option<V>) -> Option<V> {
+        resolve_subset(level, cumulative, |o| matches!(o.source, SourceKind::Record(_) | SourceKind::Push(_)))
+    }
+}
+
+// Trust one platform's observations over every other's.
+#[derive(Debug)]
+pub struct PreferPlatform(pub String);
+
+impl<V: Quantity> ConflictPolicy<V> for PreferPlatform {
+    fn resolve(&self, level: &Level<V>, cumulative: Option<V>) -> Option<V> {
+        resolve_subset(level, cumulative, |o| match &o.source {
+            SourceKind::Polling(name) | SourceKind::Record(name) | SourceKind::Push(name) => name == &self.0
+        })
+    }
+}
+
+// Assume observations happened in order of interval end - the latest to end has the final say.
+// Ties go by start, then ID - however the level happens to hold them.
+#[derive(Debug)]
+pub struct LastEndWins;
+
+impl<V: Quantity> ConflictPolicy<V> for LastEndWins {
+    fn resolve(&self, level: &Level<V>, cumulative: Option<V>) -> Option<V> {
+        let mut observations: Vec<&Observation<V>> = level.observations.iter().collect();
+        observations.sort_by(|a, b| (a.interval.1, a.interval.0, &a.id).cmp(&(b.interval.1, b.interval.0, &b.id)));
+
+        let mut value = cumulative;
+        for observation in observations {
+            value = match observation.definition {
+                DefinitionPredicate::Transition { v_1, .. } => Some(v_1), // Observer saw it end here.
+                DefinitionPredicate::Mutation { delta } => value.and_then(|v| v.combine(delta)),
+                DefinitionPredicate::Assignment { v_new } => Some(v_new),
+                DefinitionPredicate::Count { stock, v_new } => value.map(|v| v.counted(stock, v_new)),
+            };
+        }
+        return value;
+    }
+}
+
+// Ignore the level entirely - consensus carries on from before it.
+#[derive(Debug)]
+pub struct KeepPrevious;
+
+impl<V: Quantity> ConflictPolicy<V> for KeepPrevious {
+    fn resolve(&self, _level: &Level<V>, cumulative: Option<V>) -> Option<V> {
+        cumulative
+    }
+}
+
+// Samples when each observation happened from its distribution - the value most samples
+// agree on wins, with its share of them as confidence. Seeded and sampled in ID order, so a level always resolves alike.
+#[derive(Debug)]
+pub struct MostLikely {
+    pub samples: usize,
+}
+
+const SEED: u64 = 0x5EED;
+
+impl<V: Quantity> ConflictPolicy<V> for MostLikely {
+    fn resolve(&self, level: &Level<V>, cumulative: Option<V>) -> Option<V> {
+        self.weigh(level, cumulative).and_then(|likely| likely.value)
+    }
+
+    fn weigh(&self, level: &Level<V>, cumulative: Option<V>) -> Option<Likely<V>> {
+        let mut observations: Vec<&Observation<V>> = level.observations.iter().collect();
+        observations.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let mut orders: BTreeMap<Vec<usize>, usize> = BTreeMap::new();
+
+        for _ in 0..self.samples {
+            let times: Vec<f64> = observations.iter().map(|o| o.distribution.sample(o.interval, &mut rng)).collect();
+            let mut order: Vec<usize> = (0..observations.len()).collect();
+            order.sort_by(|a, b| times[*a].total_cmp(&times[*b]));
+            // Samples contradicting how observations are known to be ordered could not have happened.
+            let contradicts = order.iter().enumerate().any(|(i, a)| {
+                order[i + 1..].iter().any(|b| observations[*b].ordering(&observations[*a]) == Some(std::cmp::Ordering::Less))
+            });
+            if !contradicts {
+                *orders.entry(order).or_default() += 1;
+            }
+        }
+
+        // Orders whose transitions do not match could not have happened either.
+        let mut values: BTreeMap<V, (usize, usize, Vec<usize>)> = BTreeMap::new(); // Samples, likeliest order's samples, it.
+        let mut possible = 0;
+        for (order, count) in orders {
+            let Some(value) = replay(order.iter().map(|i| observations[*i]), cumulative) else { continue };
+            possible += count;
+            let entry = values.entry(value).or_insert((0, 0, Vec::new()));
+            entry.0 += count;
+            if count > entry.1 {
+                (entry.1, entry.2) = (count, order);
+            }
+        }
+
+        let (value, (count, _, order)) = values.into_iter().max_by_key(|(_, (count, _, _))| *count)?;
+        Some(Likely {
+            value: Some(value),
+            ordering: order.into_iter().map(|i| observations[i].id.clone()).collect(),
+            confidence: count as f64 / possible as f64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inference::explain::{Conflict, Resolution};
+    use crate::inference::history::Level;
+    use crate::inference::interval::{Interval, Moment, TimeDistribution};
+    use crate::observations::{DefinitionPredicate, Observation, ObservationId, SourceKind};
+    use super::ConflictPolicyConfig;
+
+    fn observation(source: SourceKind, definition: DefinitionPredicate, start: u64, end: u64) -> Observation {
+        Observation {
+            id: ObservationId::Sequence(source.clone(), 0),
+            definition,
+            interval: Interval(Moment(start), Moment(end)),
+            source,
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: None,
+            timestamp: None,
+        }
+    }
+
+    // A recount to 5 recorded on B, overlapping a sale A's poll saw - 4 if the recount came first, 5 if not.
+    #[test]
+    fn policies_resolve_a_conflicting_level() {
+        let level = Level {
+            interval: Interval(Moment(0), Moment(15)),
+            observations: vec![
+                observation(SourceKind::Record("B".to_string()), DefinitionPredicate::Assignment { v_new: 5 }, 0, 10),
+                observation(SourceKind::Polling("A".to_string()), DefinitionPredicate::Mutation { delta: -1 }, 5, 15),
+            ],
+        };
+        for (policy, expected) in [
+            (ConflictPolicyConfig::Strict, None),
+            (ConflictPolicyConfig::PessimisticMinimum, Some(4)),
+            (ConflictPolicyConfig::PreferRecord, Some(5)),
+            (ConflictPolicyConfig::PreferPlatform("A".to_string()), Some(9)), // Only the sale, from before the level.
+            (ConflictPolicyConfig::LastEndWins, Some(4)),
+            (ConflictPolicyConfig::KeepPrevious, Some(10)),
+        ] {
+            let (value, resolution) = level.resolution(Some(10), policy.build().as_ref());
+            assert_eq!(value, expected, "{policy:?}");
+            assert!(matches!(resolution, Resolution::Conflict { conflict: Conflict::Ambiguous { .. }, .. }), "{policy:?}");
+        }
+    }
+}
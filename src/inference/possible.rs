@@ -0,0 +1,31 @@
+use std::collections::BTreeSet;
+use serde::{Deserialize, Serialize};
+use crate::value::{Quantity, Value};
+
+// Values the consensus could have, as far as the history can tell - or why it cannot tell.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "V: Ord + Deserialize<'de>"))]
+pub enum Possible<V = Value> {
+    Known(BTreeSet<V>), // One of these - none at all if no order of the history is consistent.
+    Unknown, // Nothing has defined the value yet - it could be anything.
+    TooLarge, // A level had too many orders to enumerate - anything, until an assignment defines it again.
+}
+
+impl<V: Quantity> Possible<V> {
+    // Values a level's orders start from - None standing for a value not known.
+    pub(crate) fn inputs(&self) -> BTreeSet<Option<V>> {
+        match self {
+            Possible::Known(values) => values.iter().map(|v| Some(*v)).collect(),
+            Possible::Unknown | Possible::TooLarge => BTreeSet::from([None]),
+        }
+    }
+}
+
+impl<V: Quantity> From<Option<V>> for Possible<V> {
+    fn from(value: Option<V>) -> Self {
+        match value {
+            Some(v) => Possible::Known(BTreeSet::from([v])),
+            None => Possible::Unknown,
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound::{Excluded, Unbounded};
+use log::warn;
+use crate::inference::interval::{Deviation, Interval, Moment, TimeDistribution};
+use crate::observations::{Observation, ObservationId, SourceKind, Tick};
+
+// What is known of when observations happened relative to one another - used to narrow their intervals.
+// A change happened before the reply reporting it, polls from one source saw changes in the order sent,
+// and a compensating change (a refund, on whichever platform) came after the change it offsets.
+// A poll that saw a platform's value change bounds the one record of that platform that could have changed it -
+// a platform's value changes only by its records and our writes, and we never write while polling it.
+// Changes a platform stamped with its own clock are timed by the latest estimate of its deviation.
+// Folded observations are forgotten - what they narrowed stays narrowed until reset.
+#[derive(Debug, Default)]
+pub struct Constraints {
+    reported: BTreeMap<ObservationId, Interval>, // As observed, capped by the reply reporting it.
+    narrowed: BTreeMap<ObservationId, Interval>, // Within reported, once every constraint holds.
+    later: HashMap<ObservationId, Vec<ObservationId>>, // Observations known to happen no earlier than each.
+    earlier: HashMap<ObservationId, Vec<ObservationId>>, // Observations known to happen no later than each.
+    polls: HashMap<SourceKind, BTreeMap<Moment, ObservationId>>, // Each polling source's observations by start.
+    stamped: HashMap<ObservationId, (SourceKind, Moment, Option<Moment>)>, // Timestamp and reply of each stamped observation.
+    deviations: HashMap<SourceKind, Deviation>,
+    records: HashMap<String, Overlaps>, // Each platform's records.
+    windows: HashMap<String, Overlaps>, // Each platform's polls - each saw its value change.
+    recorded: HashMap<String, Moment>, // Each platform's record horizon - all its records starting before have arrived.
+    within: HashMap<ObservationId, Interval>, // Polls a record alone could have changed the value in, intersected.
+}
+
+// Observations by reported start - with the widest held, any overlapping an interval start within a range of it.
+#[derive(Debug, Default)]
+struct Overlaps {
+    starts: BTreeMap<Moment, Vec<ObservationId>>,
+    widest: Tick, // Never narrows - once the widest is gone, ranges are only wider than they need be.
+}
+
+impl Overlaps {
+    fn insert(&mut self, id: ObservationId, interval: Interval) {
+        self.widest = max(self.widest, interval.width());
+        self.starts.entry(interval.0).or_default().push(id);
+    }
+
+    fn remove(&mut self, id: &ObservationId, interval: Interval) -> bool {
+        let Some(ids) = self.starts.get_mut(&interval.0) else { return false };
+        let Some(at) = ids.iter().position(|i| i == id) else { return false };
+        ids.swap_remove(at);
+        if ids.is_empty() {
+            self.starts.remove(&interval.0);
+        }
+        true
+    }
+
+    // Those whose reported interval shares a moment with interval.
+    fn overlapping<'a>(&'a self, interval: Interval, reported: &'a BTreeMap<ObservationId, Interval>) -> impl Iterator<Item = &'a ObservationId> + 'a {
+        let from = interval.0.checked_sub(self.widest).unwrap_or(Moment(0));
+        self.starts.range(from..=interval.1)
+            .flat_map(|(_, ids)| ids)
+            .filter(move |id| reported.get(*id).is_some_and(|i| i.intersects(&interval)))
+    }
+}
+
+impl Constraints {
+    pub fn new() -> Self {
+        Constraints { reported: BTreeMap::new(), narrowed: BTreeMap::new(), later: HashMap::new(), earlier: HashMap::new(), polls: HashMap::new(),
+            stamped: HashMap::new(), deviations: HashMap::new(), records: HashMap::new(), windows: HashMap::new(), recorded: HashMap::new(),
+            within: HashMap::new() }
+    }
+
+    // Interval of an accepted observation, as reported - before any narrowing.
+    pub fn observed(&self, id: &ObservationId) -> Option<Interval> {
+        self.reported.get(id).copied()
+    }
+
+    pub fn deviations(&self) -> &HashMap<SourceKind, Deviation> {
+        &self.deviations
+    }
+
+    // Interval of an accepted observation, as narrowed so far.
+    pub fn interval(&self, id: &ObservationId) -> Option<Interval> {
+        self.narrowed.get(id).copied()
+    }
+
+    // Where in its interval a stamped observation likely happened - None until its source's deviation is known.
+    pub fn distribution<V>(&self, observation: &Observation<V>) -> Option<TimeDistribution> {
+        Some(self.deviations.get(&observation.source)?.distribution(observation.timestamp?))
+    }
+
+    // Interval, as reported, of a poll from the same source sent at the same moment - none can be.
+    pub fn same_send<V>(&self, observation: &Observation<V>) -> Option<Interval> {
+        let id = self.polls.get(&observation.source)?.get(&observation.interval.0)?;
+        self.reported.get(id).copied()
+    }
+
+    // Takes in a new observation's constraints - returns the earlier observations it narrowed.
+    pub fn add<V>(&mut self, observation: &Observation<V>) -> Vec<ObservationId> {
+        let id = &observation.id;
+        let interval = self.reported(id, observation.interval, &observation.source, observation.timestamp, observation.reported);
+        self.reported.insert(id.clone(), interval);
+        if let Some(timestamp) = observation.timestamp {
+            self.stamped.insert(id.clone(), (observation.source.clone(), timestamp, observation.reported));
+        }
+        self.narrowed.insert(id.clone(), interval);
+
+        let platform = match &observation.source {
+            SourceKind::Polling(platform) => {
+                let polls = self.polls.entry(observation.source.clone()).or_default();
+                let start = observation.interval.0;
+                let before = polls.range(..start).next_back().map(|(_, id)| id.clone());
+                let after = polls.range((Excluded(start), Unbounded)).next().map(|(_, id)| id.clone());
+                polls.insert(start, id.clone());
+                if let Some(before) = before {
+                    self.link(before, id.clone());
+                }
+                if let Some(after) = after {
+                    self.link(id.clone(), after);
+                }
+                self.windows.entry(platform.clone()).or_default().insert(id.clone(), interval);
+                Some(platform)
+            }
+            SourceKind::Record(platform) => {
+                self.records.entry(platform.clone()).or_default().insert(id.clone(), interval);
+                Some(platform)
+            }
+            SourceKind::Push(_) => None
+        };
+        if let Some(compensated) = &observation.compensates {
+            self.link(compensated.clone(), id.clone()); // Its counterpart may not have arrived yet.
+        }
+
+        // A record arriving, or a poll, can change which records are alone in the polls around it.
+        let rebound = platform.map(|platform| self.pair(platform, [interval])).unwrap_or_default();
+        let mut pending = vec![id.clone()];
+        pending.extend(self.later.get(id).into_iter().flatten().cloned());
+        pending.extend(self.earlier.get(id).into_iter().flatten().cloned());
+        let mut narrowed = self.propagate(pending);
+        narrowed.extend(self.reset(rebound));
+        narrowed.sort();
+        narrowed.dedup();
+        narrowed.retain(|n| n != id);
+        narrowed
+    }
+
+    // Drops a withdrawn observation's constraints - returns every observation whose interval changed.
+    pub fn remove(&mut self, id: &ObservationId) -> Vec<ObservationId> {
+        if !self.reported.contains_key(id) {
+            return Vec::new();
+        }
+        // What it narrowed may have relied on it - only those linked to it, however indirectly.
+        let mut linked: Vec<ObservationId> = self.later.get(id).into_iter().chain(self.earlier.get(id)).flatten().cloned().collect();
+        if let Some((platform, reported)) = self.unlink(id) {
+            linked.extend(self.pair(&platform, [reported]));
+        }
+        self.reset(linked)
+    }
+
+    // Drops folded observations' constraints - they can no longer be reordered, nor anything retimed or retracted by them.
+    pub fn forget<'a>(&mut self, ids: impl IntoIterator<Item = &'a ObservationId>) {
+        for id in ids {
+            self.unlink(id);
+        }
+    }
+
+    // Platform's records have all arrived up to horizon - returns every observation whose interval changed.
+    pub fn horizon(&mut self, source: &SourceKind, horizon: Moment) -> Vec<ObservationId> {
+        let SourceKind::Record(platform) = source else { return Vec::new() };
+        let previous = self.recorded.insert(platform.clone(), horizon);
+        if let Some(previous) = previous.filter(|p| *p > horizon) {
+            self.recorded.insert(platform.clone(), previous);
+            return Vec::new();
+        }
+        // Only polls ending from the last horizon on have come within it.
+        let rebound = self.pair(platform, [Interval(previous.unwrap_or(Moment(0)), horizon)]);
+        self.reset(rebound)
+    }
+
+    // Retimes a source's stamped observations by a revised deviation - returns every observation whose interval changed.
+    pub fn retime(&mut self, source: SourceKind, deviation: Deviation) -> Vec<ObservationId> {
+        if self.deviations.insert(source.clone(), deviation) == Some(deviation) {
+            return Vec::new();
+        }
+        let retimed: Vec<(ObservationId, Moment, Option<Moment>)> = self.stamped.iter()
+            .filter(|(_, (stamped_by, _, _))| *stamped_by == source)
+            .map(|(id, (_, timestamp, reply))| (id.clone(), *timestamp, *reply))
+            .collect();
+        let mut reset = Vec::with_capacity(retimed.len());
+        let mut around = Vec::new();
+        for (id, timestamp, reply) in retimed {
+            let interval = self.reported(&id, deviation.interval(timestamp), &source, Some(timestamp), reply);
+            let previous = self.reported.insert(id.clone(), interval);
+            if let (Some((_, held)), Some(previous)) = (self.held(&source), previous) {
+                held.remove(&id, previous);
+                held.insert(id.clone(), interval);
+                around.extend([previous, interval]);
+            }
+            reset.push(id);
+        }
+        if let Some((platform, _)) = self.held(&source) {
+            reset.extend(self.pair(&platform, around)); // Retimed records may overlap other polls.
+        }
+        self.reset(reset) // Both wider and narrower intervals change what propagation allows.
+    }
+
+    // Interval as observed - timed by its source's clock if stamped, and capped by the reply reporting it.
+    fn reported(&self, id: &ObservationId, observed: Interval, source: &SourceKind, timestamp: Option<Moment>, reply: Option<Moment>) -> Interval {
+        let interval = match (timestamp, self.deviations.get(source)) {
+            (Some(timestamp), Some(deviation)) => deviation.interval(timestamp),
+            _ => observed
+        };
+        match reply {
+            Some(reply) if reply < interval.0 => {
+                warn!("Inference - {id:?} reported before it could have happened, keeping {interval:?}");
+                interval
+            }
+            Some(reply) if reply < interval.1 => Interval(interval.0, reply),
+            _ => interval
+        }
+    }
+
+    // Forgets every constraint on an observation - returns the platform whose records or polls it was among, with its interval there.
+    fn unlink(&mut self, id: &ObservationId) -> Option<(String, Interval)> {
+        let reported = self.reported.remove(id)?;
+        self.narrowed.remove(id);
+        self.stamped.remove(id);
+        self.within.remove(id);
+        for after in self.later.remove(id).unwrap_or_default() {
+            if let Some(earlier) = self.earlier.get_mut(&after) {
+                earlier.retain(|e| e != id);
+            }
+        }
+        for before in self.earlier.remove(id).unwrap_or_default() {
+            if let Some(later) = self.later.get_mut(&before) {
+                later.retain(|l| l != id);
+            }
+        }
+        if let Some(polls) = self.polls.values_mut().find(|polls| polls.get(&reported.0) == Some(id)) {
+            // Polls either side are still ordered - now directly.
+            polls.remove(&reported.0);
+            let before = polls.range(..reported.0).next_back().map(|(_, id)| id.clone());
+            let after = polls.range((Excluded(reported.0), Unbounded)).next().map(|(_, id)| id.clone());
+            if let (Some(before), Some(after)) = (before, after) {
+                self.link(before, after);
+            }
+        }
+        self.records.iter_mut().chain(self.windows.iter_mut())
+            .find_map(|(platform, held)| held.remove(id, reported).then(|| (platform.clone(), reported)))
+    }
+
+    // Platform a source's observations are held for, and where - its records, or its polls.
+    fn held(&mut self, source: &SourceKind) -> Option<(String, &mut Overlaps)> {
+        let (platform, held) = match source {
+            SourceKind::Polling(platform) => (platform, self.windows.get_mut(platform)?),
+            SourceKind::Record(platform) => (platform, self.records.get_mut(platform)?),
+            SourceKind::Push(_) => return None,
+        };
+        Some((platform.clone(), held))
+    }
+
+    // Bounds each of a platform's records by the polls it alone overlaps - once all records that could have have arrived.
+    // Only records overlapping what changed, or sharing a poll with it, can be bound differently. Returns those whose bound changed.
+    fn pair(&mut self, platform: &str, changed: impl IntoIterator<Item = Interval>) -> Vec<ObservationId> {
+        let Some(records) = self.records.get(platform) else { return Vec::new() };
+        let mut near: HashSet<ObservationId> = HashSet::new();
+        for interval in changed {
+            near.extend(records.overlapping(interval, &self.reported).cloned());
+            for poll in self.windows.get(platform).into_iter().flat_map(|polls| polls.overlapping(interval, &self.reported)) {
+                near.extend(records.overlapping(self.reported[poll], &self.reported).cloned());
+            }
+        }
+        let mut rebound = Vec::new();
+        for record in near {
+            let within = self.within(platform, &record);
+            let previous = match within {
+                Some(window) => self.within.insert(record.clone(), window),
+                None => self.within.remove(&record)
+            };
+            if previous != within {
+                rebound.push(record);
+            }
+        }
+        rebound
+    }
+
+    // Polls a record is alone in, of those ending before its platform's record horizon, intersected.
+    fn within(&self, platform: &str, record: &ObservationId) -> Option<Interval> {
+        let recorded = self.recorded.get(platform)?;
+        let (records, polls) = (self.records.get(platform)?, self.windows.get(platform)?);
+        let mut within: Option<Interval> = None;
+        for poll in polls.overlapping(*self.reported.get(record)?, &self.reported) {
+            let window = self.reported[poll];
+            if window.1 >= *recorded {
+                continue;
+            }
+            let mut overlapping = records.overlapping(window, &self.reported);
+            if let (Some(_), None) = (overlapping.next(), overlapping.next()) {
+                // Changed the value in every poll it is alone in.
+                within = Some(match within {
+                    Some(w) => w.intersection(&window).unwrap_or_else(|| {
+                        warn!("Inference - {record:?} is alone in polls sharing no moment, keeping {w:?}");
+                        w
+                    }),
+                    None => window
+                });
+            }
+        }
+        within
+    }
+
+    // Reported interval, within whatever polls bound it.
+    fn bounded(&self, id: &ObservationId) -> Option<Interval> {
+        let reported = self.reported.get(id)?;
+        Some(self.within.get(id).and_then(|w| w.intersection(reported)).unwrap_or(*reported))
+    }
+
+    // Starts over from what was reported for everything linked to seeds - returns every observation whose interval changed.
+    // Observations not linked to them, however indirectly, cannot have been narrowed by them.
+    fn reset(&mut self, seeds: Vec<ObservationId>) -> Vec<ObservationId> {
+        let mut linked: HashSet<ObservationId> = HashSet::new();
+        let mut pending = seeds;
+        while let Some(id) = pending.pop() {
+            if !self.reported.contains_key(&id) || !linked.insert(id.clone()) {
+                continue;
+            }
+            pending.extend(self.later.get(&id).into_iter().chain(self.earlier.get(&id)).flatten().cloned());
+        }
+        let mut previous = Vec::with_capacity(linked.len());
+        for id in &linked {
+            let interval = self.bounded(id).unwrap();
+            previous.push((id.clone(), self.narrowed.insert(id.clone(), interval)));
+        }
+        self.propagate(linked.into_iter().collect());
+        previous.into_iter()
+            .filter(|(id, interval)| self.narrowed.get(id) != interval.as_ref())
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn link(&mut self, before: ObservationId, after: ObservationId) {
+        self.later.entry(before.clone()).or_default().push(after.clone());
+        self.earlier.entry(after).or_default().push(before);
+    }
+
+    // Narrows until every constraint between accepted observations holds - returns those narrowed.
+    fn propagate(&mut self, mut pending: Vec<ObservationId>) -> Vec<ObservationId> {
+        let mut narrowed = Vec::new();
+        while let Some(id) = pending.pop() {
+            let Some(interval) = self.interval(&id) else { continue }; // Not arrived yet.
+            for after in self.later.get(&id).cloned().unwrap_or_default() {
+                // Cannot have happened before the earliest the change it follows could have.
+                if self.narrow(&after, |i| Interval(max(i.0, interval.0), i.1)) {
+                    pending.push(after.clone());
+                    if !narrowed.contains(&after) {
+                        narrowed.push(after);
+                    }
+                }
+            }
+            for before in self.earlier.get(&id).cloned().unwrap_or_default() {
+                // Nor can what it follows have happened after the latest it could have.
+                if self.narrow(&before, |i| Interval(i.0, min(i.1, interval.1))) {
+                    pending.push(before.clone());
+                    if !narrowed.contains(&before) {
+                        narrowed.push(before);
+                    }
+                }
+            }
+        }
+        narrowed
+    }
+
+    // Contradictory constraints leave the interval as it was - the observations cannot all be right.
+    fn narrow(&mut self, id: &ObservationId, to: impl Fn(Interval) -> Interval) -> bool {
+        let Some(current) = self.narrowed.get_mut(id) else { return false };
+        let narrower = to(*current);
+        if narrower == *current {
+            return false;
+        }
+        if !narrower.is_valid() {
+            warn!("Inference - Constraints on {id:?} contradict, keeping {current:?}");
+            return false;
+        }
+        *current = narrower;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inference::interval::{Deviation, Interval, Moment, TimeDistribution};
+    use crate::observations::{DefinitionPredicate, Observation, ObservationId, SourceKind};
+    use super::Constraints;
+
+    fn observation(id: &str, source: SourceKind, start: u64, end: u64) -> Observation {
+        Observation {
+            id: ObservationId::Change(source.clone(), id.to_string()),
+            definition: DefinitionPredicate::Mutation { delta: -1 },
+            interval: Interval(Moment(start), Moment(end)),
+            source,
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: None,
+            timestamp: None,
+        }
+    }
+
+    fn record(id: &str, start: u64, end: u64) -> Observation {
+        observation(id, SourceKind::Record("Square".to_string()), start, end)
+    }
+
+    #[test]
+    fn lone_record_is_bounded_by_the_poll_that_saw_it() {
+        let mut constraints = Constraints::new();
+        let poll = observation("poll", SourceKind::Polling("Square".to_string()), 10, 20);
+        let (sale, later) = (record("sale", 0, 40), record("later", 50, 60));
+        for observation in [&poll, &sale, &later] {
+            constraints.add(observation);
+        }
+        assert_eq!(constraints.interval(&sale.id), Some(sale.interval)); // Another record may yet arrive within the poll.
+
+        let horizon = SourceKind::Record("Square".to_string());
+        assert_eq!(constraints.horizon(&horizon, Moment(30)), vec![sale.id.clone()]);
+        assert_eq!(constraints.interval(&sale.id), Some(Interval(Moment(10), Moment(20))));
+        assert_eq!(constraints.interval(&later.id), Some(later.interval));
+
+        assert_eq!(constraints.remove(&poll.id), vec![sale.id.clone()]);
+        assert_eq!(constraints.interval(&sale.id), Some(sale.interval));
+    }
+
+    #[test]
+    fn retimed_records_are_paired_where_they_now_lie() {
+        let mut constraints = Constraints::new();
+        let poll = observation("poll", SourceKind::Polling("Square".to_string()), 10, 20);
+        let sale = Observation { timestamp: Some(Moment(15)), ..record("sale", 0, 40) };
+        constraints.add(&poll);
+        constraints.add(&sale);
+        constraints.horizon(&SourceKind::Record("Square".to_string()), Moment(30));
+        assert_eq!(constraints.interval(&sale.id), Some(Interval(Moment(10), Moment(20))));
+
+        // Its clock turns out to run well behind - it happened after the poll.
+        let deviation = Deviation { min: -90, max: -80 };
+        assert_eq!(constraints.retime(SourceKind::Record("Square".to_string()), deviation), vec![sale.id.clone()]);
+        assert!(!deviation.interval(Moment(15)).intersects(&poll.interval));
+        assert_eq!(constraints.interval(&sale.id), Some(deviation.interval(Moment(15))));
+    }
+
+    #[test]
+    fn records_sharing_a_poll_are_not_bounded() {
+        let mut constraints = Constraints::new();
+        let poll = observation("poll", SourceKind::Polling("Square".to_string()), 10, 20);
+        let (sale, refund) = (record("sale", 0, 40), record("refund", 15, 45));
+        for observation in [&poll, &sale, &refund] {
+            constraints.add(observation);
+        }
+        assert!(constraints.horizon(&SourceKind::Record("Square".to_string()), Moment(50)).is_empty());
+        assert_eq!(constraints.interval(&sale.id), Some(sale.interval));
+        assert_eq!(constraints.interval(&refund.id), Some(refund.interval));
+    }
+
+    #[test]
+    fn removal_resets_only_what_was_linked() {
+        let mut constraints = Constraints::new();
+        let sale = record("sale", 20, 30);
+        let refund = Observation { compensates: Some(sale.id.clone()), ..observation("refund", SourceKind::Push("Shopify".to_string()), 0, 40) };
+        let unrelated = observation("unrelated", SourceKind::Push("Shopify".to_string()), 0, 50);
+        for observation in [&sale, &refund, &unrelated] {
+            constraints.add(observation);
+        }
+        assert_eq!(constraints.interval(&refund.id), Some(Interval(Moment(20), Moment(40))));
+
+        assert_eq!(constraints.remove(&sale.id), vec![refund.id.clone()]);
+        assert_eq!(constraints.interval(&refund.id), Some(refund.interval));
+        assert_eq!(constraints.interval(&unrelated.id), Some(unrelated.interval));
+    }
+
+    #[test]
+    fn folded_observations_are_forgotten() {
+        let mut constraints = Constraints::new();
+        let sale = record("sale", 20, 30);
+        let refund = Observation { compensates: Some(sale.id.clone()), ..record("refund", 0, 40) };
+        constraints.add(&sale);
+        constraints.add(&refund);
+
+        constraints.forget([&sale.id]);
+        assert_eq!(constraints.observed(&sale.id), None);
+        assert_eq!(constraints.interval(&refund.id), Some(Interval(Moment(20), Moment(40)))); // Still as it narrowed it.
+        assert!(!constraints.later.contains_key(&sale.id));
+        assert_eq!(constraints.earlier.get(&refund.id), Some(&vec![]));
+        assert!(constraints.records.values().flat_map(|held| held.starts.values().flatten()).all(|id| *id != sale.id));
+    }
+}
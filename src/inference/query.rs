@@ -0,0 +1,62 @@
+use std::collections::BTreeSet;
+use serde::Serialize;
+use crate::inference::history::Level;
+use crate::inference::interval::Moment;
+use crate::inference::policy::ConflictPolicy;
+use crate::inference::possible::Possible;
+use crate::value::{Quantity, Value};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum ValueAt<V = Value> {
+    Exact(Option<V>), // No level was under way at the moment - the consensus then, None if unknown.
+    Range { low: V, high: V }, // Part way through unordered levels - somewhere between.
+    Folded, // Before the watermark - only the snapshot after it is kept.
+}
+
+// Value at moment, over levels in execution order - starting from init and its possible values.
+// Levels ending before moment certainly happened, those starting after it certainly had not.
+// Any between could have been part way through, in any order consistent with what is known.
+pub(crate) fn value_at<'a, V: Quantity>(
+    levels: impl IntoIterator<Item = &'a Level<V>>,
+    init: Option<V>,
+    possible: Possible<V>,
+    policy: &dyn ConflictPolicy<V>,
+    moment: Moment
+) -> ValueAt<V> {
+    let (mut cumulative, mut possible) = (init, possible);
+    let mut reach = None;
+    let mut during: Option<BTreeSet<Option<V>>> = None; // Values while levels were under way.
+
+    for level in levels {
+        reach = reach.max(Some(level.interval.1));
+        if reach < Some(moment) {
+            cumulative = level.resolve(cumulative, policy);
+            possible = level.possible(&possible);
+            continue;
+        }
+        if level.interval.0 > moment {
+            break; // Levels start in order - none after this had begun either.
+        }
+
+        let Some(partial) = level.partial(&possible.inputs(), moment) else {
+            return ValueAt::Exact(None); // Too large to tell.
+        };
+        if level.observations.iter().any(|o| o.interval.1 < moment) {
+            during = Some(BTreeSet::new()); // Level had begun - so every level before it had finished.
+        }
+        during.get_or_insert_with(BTreeSet::new).extend(partial);
+        possible = level.possible(&possible);
+    }
+
+    let Some(during) = during else {
+        return ValueAt::Exact(cumulative);
+    };
+    if during.contains(&None) {
+        return ValueAt::Exact(None);
+    }
+    match (during.first(), during.last()) {
+        (Some(Some(low)), Some(Some(high))) if low == high => ValueAt::Exact(Some(*low)),
+        (Some(Some(low)), Some(Some(high))) => ValueAt::Range { low: *low, high: *high },
+        _ => ValueAt::Exact(None), // No order of the levels is consistent.
+    }
+}
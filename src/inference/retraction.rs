@@ -0,0 +1,105 @@
+use std::collections::{HashMap, VecDeque};
+use serde::Serialize;
+use crate::error::InferenceError;
+use crate::inference::interval::{Interval, Moment, TimeDistribution};
+use crate::observations::{DefinitionPredicate, Observation, ObservationId, Retraction, Tick};
+use crate::value::{Quantity, Value};
+
+// Milliseconds of folding a retraction waits for what it withdraws - and a folded observation can still be retracted for.
+const RETRACTABLE_FOR: Tick = 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Retracted<V = Value> {
+    pub observation: Option<Observation<V>>, // None until the retracted observation arrives, if ever - or if folded, and compensated.
+    pub retraction: Retraction,
+    #[serde(skip)]
+    waiting_from: Option<Moment>, // Fold it began waiting at - None until anything folds.
+}
+
+// Every retraction received, with what it withdrew - kept so explanations can show both.
+// A retraction may arrive before what it retracts - that observation is then dropped on arrival.
+// Pruned once what they withdrew could only arrive behind the fold, or has been waited for long enough.
+#[derive(Debug)]
+pub struct Retractions<V = Value> {
+    retracted: Vec<Retracted<V>>, // In order received.
+    index: HashMap<ObservationId, usize>, // Retracted observation ID to its position in retracted.
+    folded: VecDeque<(Moment, ObservationId)>, // Recently folded observations, by the fold they went into.
+    definitions: HashMap<ObservationId, DefinitionPredicate<V>>, // What each recently folded observation did.
+}
+
+impl<V: Clone> Retractions<V> {
+    pub fn new() -> Self {
+        Retractions { retracted: Vec::new(), index: HashMap::new(), folded: VecDeque::new(), definitions: HashMap::new() }
+    }
+
+    pub fn withdraws(&self, id: &ObservationId) -> bool {
+        self.index.contains_key(id)
+    }
+
+    pub fn record(&mut self, retraction: Retraction, observation: Option<Observation<V>>, folded_until: Option<Moment>) {
+        self.index.insert(retraction.retracts.clone(), self.retracted.len());
+        self.retracted.push(Retracted { observation, retraction, waiting_from: folded_until });
+    }
+
+    // A retracted observation turning up after its retraction - true the first time it does.
+    pub fn arrived(&mut self, observation: Observation<V>) -> bool {
+        let Some(i) = self.index.get(&observation.id) else { return false };
+        let retracted = &mut self.retracted[*i];
+        if retracted.observation.is_some() {
+            return false;
+        }
+        retracted.observation = Some(observation);
+        true
+    }
+
+    pub fn all(&self) -> &[Retracted<V>] {
+        &self.retracted
+    }
+
+    // Observations folded at folded_until - remembered a while, in case they are retracted.
+    pub fn folded<'a>(&mut self, observations: impl IntoIterator<Item = &'a Observation<V>>, folded_until: Moment) where V: 'a {
+        for observation in observations {
+            self.folded.push_back((folded_until, observation.id.clone()));
+            self.definitions.insert(observation.id.clone(), observation.definition.clone());
+        }
+    }
+
+    pub fn prune(&mut self, folded_until: Moment) {
+        let expired = |from: Moment| from.checked_add(RETRACTABLE_FOR).is_some_and(|until| until < folded_until);
+        while let Some((_, id)) = self.folded.front().filter(|(at, _)| expired(*at)) {
+            self.definitions.remove(id);
+            self.folded.pop_front();
+        }
+
+        let before = self.retracted.len();
+        self.retracted.retain_mut(|retracted| match &retracted.observation {
+            Some(observation) => observation.interval.0 >= folded_until, // A refetch of it is rejected behind the fold.
+            None => !expired(*retracted.waiting_from.get_or_insert(folded_until)),
+        });
+        if self.retracted.len() != before {
+            self.index = self.retracted.iter().enumerate().map(|(i, r)| (r.retraction.retracts.clone(), i)).collect();
+        }
+    }
+}
+
+impl<V: Quantity> Retractions<V> {
+    // Undoes a recently folded observation by its inverse, just past the fold - None if it was not folded, or is forgotten.
+    // Only a mutation has one - anything else folded is irreversible.
+    pub fn compensation(&self, retraction: &Retraction, folded_until: Moment) -> Option<Result<Observation<V>, InferenceError>> {
+        let irreversible = InferenceError::Irreversible { id: retraction.retracts.clone(), folded_until };
+        let delta = match self.definitions.get(&retraction.retracts)? {
+            DefinitionPredicate::Mutation { delta } => delta.inverse(),
+            _ => None
+        };
+        Some(delta.ok_or(irreversible).map(|delta| Observation {
+            id: retraction.id.clone(),
+            definition: DefinitionPredicate::Mutation { delta },
+            interval: Interval(folded_until, folded_until),
+            source: retraction.source.clone(),
+            compensates: None, // What it offsets is forgotten - it follows everything folded anyway.
+            distribution: TimeDistribution::Uniform,
+            reported: None,
+            timestamp: None,
+        }))
+    }
+}
@@ -0,0 +1,36 @@
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use crate::inference::interval::Moment;
+use crate::observations::SourceKind;
+
+// Latest horizon reported by each source - no observation it has yet to deliver may start before it.
+// Expected sources hold the watermark back until they have reported - any other must report before its first observation.
+#[derive(Debug, Default)]
+pub struct Watermark {
+    horizons: HashMap<SourceKind, Moment>,
+    expected: HashSet<SourceKind>,
+}
+
+impl Watermark {
+    pub fn new() -> Self {
+        Watermark { horizons: HashMap::new(), expected: HashSet::new() }
+    }
+
+    pub fn expect(&mut self, source: SourceKind) {
+        self.expected.insert(source);
+    }
+
+    // Horizons only move forward - a source reporting an earlier one has not taken back its promise.
+    pub fn advance(&mut self, source: SourceKind, horizon: Moment) {
+        let current = self.horizons.entry(source).or_insert(horizon);
+        *current = max(*current, horizon);
+    }
+
+    // Moment before which nothing can still arrive - None until every expected source, and any other, has reported.
+    pub fn get(&self) -> Option<Moment> {
+        if self.expected.iter().any(|source| !self.horizons.contains_key(source)) {
+            return None;
+        }
+        self.horizons.values().min().cloned()
+    }
+}
@@ -0,0 +1,230 @@
+use std::fs::{rename, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, JournalError};
+use crate::inference::interval::Deviation;
+use crate::inference::Snapshot;
+use crate::observations::{Observation, Report, Retraction, SourceKind};
+use crate::value::{Quantity, Target, Value};
+
+const HEADER: usize = 8; // Payload length and checksum, both u32 little endian.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "V: Ord + Deserialize<'de>"))]
+pub enum Entry<V = Value> {
+    Observation(Target, Observation<V>), // Accepted into the target's history.
+    Retraction(Target, Retraction), // Accepted by the target's history.
+    Deviation(Target, SourceKind, Deviation), // Revised for the target's history.
+    Consensus(Target, Option<V>), // Published for the target.
+    Snapshot(Target, Snapshot<V>), // Target restarts from here - earlier entries for it are obsolete.
+}
+
+impl<V> Entry<V> {
+    // Horizons are not kept - sources report them afresh after a restart.
+    pub fn report(target: Target, report: Report<V>) -> Option<Self> {
+        match report {
+            Report::Observed(observation) => Some(Entry::Observation(target, observation)),
+            Report::Retracted(retraction) => Some(Entry::Retraction(target, retraction)),
+            Report::Deviation(source, deviation) => Some(Entry::Deviation(target, source, deviation)),
+            Report::Horizon(..) => None,
+        }
+    }
+
+    pub fn target(&self) -> &Target {
+        match self {
+            Entry::Observation(target, _) | Entry::Retraction(target, _) | Entry::Deviation(target, _, _) => target,
+            Entry::Consensus(target, _) | Entry::Snapshot(target, _) => target
+        }
+    }
+}
+
+// Append-only log of entries, each framed with its length and a CRC-32 of its payload.
+// A crash mid-append leaves a torn final record - replay truncates it and carries on from there.
+// A damaged record with intact ones after it was not torn by a crash - replay refuses it rather than lose them.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    pub fn open(path: impl AsRef<Path>) -> Result<Journal, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).append(true).create(true).open(&path).map_err(JournalError::Io)?;
+        Ok(Journal { path, file })
+    }
+
+    // Every entry in order - a torn tail is cut off.
+    pub fn replay<V: Quantity>(&mut self) -> Result<Vec<Entry<V>>, Error> {
+        let mut bytes = Vec::new();
+        self.file.seek(SeekFrom::Start(0)).map_err(JournalError::Io)?;
+        self.file.read_to_end(&mut bytes).map_err(JournalError::Io)?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            match decode(&bytes[offset..]) {
+                Some((entry, used)) => {
+                    entries.push(entry);
+                    offset += used;
+                }
+                None => {
+                    if let Some(resumes) = (offset + 1..bytes.len()).find(|at| decode::<V>(&bytes[*at..]).is_some()) {
+                        return Err(JournalError::Corrupt { offset, resumes }.into());
+                    }
+                    warn!("Journal - Torn tail at byte {offset} of {}, truncating.", bytes.len());
+                    self.file.set_len(offset as u64).map_err(JournalError::Io)?;
+                    self.file.sync_all().map_err(JournalError::Io)?;
+                    break;
+                }
+            }
+        }
+        info!("Journal - Replayed {} entries from {:?}", entries.len(), self.path);
+        Ok(entries)
+    }
+
+    // Buffered by the OS until the next sync.
+    pub fn append<V: Quantity>(&mut self, entry: &Entry<V>) -> Result<(), Error> {
+        let payload = serde_json::to_vec(entry).map_err(JournalError::Json)?;
+        let mut record = Vec::with_capacity(HEADER + payload.len());
+        record.extend((payload.len() as u32).to_le_bytes());
+        record.extend(checksum(&payload).to_le_bytes());
+        record.extend(payload);
+        self.file.write_all(&record).map_err(JournalError::Io)?;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data().map_err(|e| JournalError::Io(e).into())
+    }
+
+    // Rewrites the journal as just these entries - written aside first, so a crash keeps one whole copy.
+    // The rename is only durable once the directory holding it is synced.
+    pub fn compact<V: Quantity>(&mut self, entries: &[Entry<V>]) -> Result<(), Error> {
+        let aside = self.path.with_extension("compact");
+        let mut compacted = Journal { path: aside.clone(), file: File::create(&aside).map_err(JournalError::Io)? };
+        for entry in entries {
+            compacted.append(entry)?;
+        }
+        compacted.sync()?;
+        rename(&aside, &self.path).map_err(JournalError::Io)?;
+        let directory = self.path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(directory).and_then(|d| d.sync_all()).map_err(JournalError::Io)?;
+        *self = Journal::open(&self.path)?;
+        Ok(())
+    }
+}
+
+// Entries each target still needs - those after its latest snapshot, with the snapshot itself.
+pub fn live<V>(entries: Vec<Entry<V>>) -> Vec<Entry<V>> {
+    let mut kept: Vec<Entry<V>> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if let Entry::Snapshot(target, _) = &entry {
+            kept.retain(|e| e.target() != target);
+        }
+        kept.push(entry);
+    }
+    kept
+}
+
+fn decode<V: Quantity>(bytes: &[u8]) -> Option<(Entry<V>, usize)> {
+    if bytes.len() < HEADER {
+        return None;
+    }
+    let length = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+    let expected = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    let payload = bytes.get(HEADER..HEADER + length)?;
+    if checksum(payload) != expected {
+        return None;
+    }
+    let entry = serde_json::from_slice(payload).ok()?;
+    Some((entry, HEADER + length))
+}
+
+// CRC-32 (IEEE), bitwise - journal records are small.
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, remove_file, write};
+    use std::path::PathBuf;
+    use uuid::Uuid;
+    use crate::error::{Error, JournalError};
+    use crate::inference::Snapshot;
+    use super::{checksum, live, Entry, Journal};
+
+    fn journal(entries: &[Entry]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("journal-{}.log", Uuid::new_v4()));
+        let mut journal = Journal::open(&path).unwrap();
+        for entry in entries {
+            journal.append(entry).unwrap();
+        }
+        journal.sync().unwrap();
+        path
+    }
+
+    fn consensus(n: i64) -> Entry {
+        Entry::Consensus(("Location".to_string(), "Item".to_string()), Some(n))
+    }
+
+    fn published(entries: &[Entry]) -> Vec<Option<i64>> {
+        entries.iter().filter_map(|e| match e { Entry::Consensus(_, value) => Some(*value), _ => None }).collect()
+    }
+
+    #[test]
+    fn checksum_is_crc_32() {
+        assert_eq!(checksum(b"123456789"), 0xCBF43926);
+        assert_eq!(checksum(b""), 0);
+    }
+
+    #[test]
+    fn torn_tail_is_truncated() {
+        let path = journal(&[consensus(1), consensus(2)]);
+        let whole = read(&path).unwrap();
+        let mut torn = whole.clone();
+        torn.extend(&whole[..whole.len() / 4]); // Half a third record.
+        write(&path, &torn).unwrap();
+
+        let entries = Journal::open(&path).unwrap().replay().unwrap();
+        assert_eq!(published(&entries), vec![Some(1), Some(2)]);
+        assert_eq!(read(&path).unwrap(), whole);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damage_before_intact_records_is_refused() {
+        let path = journal(&[consensus(1), consensus(2), consensus(3)]);
+        let mut damaged = read(&path).unwrap();
+        let second = damaged.len() / 3 + 10; // Within the second record's payload - all three are alike in length.
+        damaged[second] ^= 0xFF;
+        write(&path, &damaged).unwrap();
+
+        let replayed = Journal::open(&path).unwrap().replay::<i64>();
+        assert!(matches!(replayed, Err(Error::Journal(JournalError::Corrupt { .. }))));
+        assert_eq!(read(&path).unwrap(), damaged); // Left as it was.
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn snapshots_obsolete_what_came_before() {
+        let target = ("Location".to_string(), "Item".to_string());
+        let other = ("Location".to_string(), "Other".to_string());
+        let entries = live(vec![
+            consensus(1),
+            Entry::Consensus(other.clone(), Some(5)),
+            Entry::Snapshot(target.clone(), Snapshot::initial(Some(2))),
+            consensus(3),
+        ]);
+        assert_eq!(published(&entries), vec![Some(5), Some(3)]);
+    }
+}
@@ -1,162 +1,178 @@
 extern crate core;
-// #[derive(Debug, Serialize, Deserialize, Clone)]
-// pub struct Config {
-//     pub(crate) profiling_directory: String,
-//     pub(crate) observers: Vec<(String, SquareObserverConfig)>
-// }
 
 mod observations;
 mod coordinator;
+mod error;
+mod journal;
 mod value;
 mod workers;
 mod testing;
 mod observers;
 mod inference;
 
-use std::cmp::max;
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::{create_dir_all, remove_file, File};
+use std::io::ErrorKind;
 use std::path::Path;
 use std::sync::Arc;
-use chrono::{TimeDelta, Utc};
-use futures::poll;
-use log::{debug, info};
+use std::time::Duration;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, watch, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::runtime;
 use tokio::task::JoinSet;
-use crate::inference::history::{NewHistory};
-use crate::observations::{DefinitionPredicate, PollingInterpretation, Tick};
-use crate::observers::mocked::mock_writer::{instant_write, InstantWriter};
+use tokio::time::{sleep, Instant};
+use crate::coordinator::{coordinator, Counted, Explainer, Publisher};
+use crate::error::{ConfigError, Error};
+use crate::inference::history::NewHistory;
+use crate::inference::Inference;
+use crate::journal::Journal;
+use crate::inference::policy::ConflictPolicyConfig;
+use crate::observations::{PollingInterpretation, SourceKind};
+use crate::observers::mocked::live::LivePlatform;
 use crate::observers::mocked::poll_platform::{MockPlatform, MockPlatformConfig};
-use crate::observers::mocked::polling::MockPoller;
-use crate::observers::mocked::record::MockRecordPoller;
 use crate::observers::mocked::record_platform::{MockRecordPlatform, MockRecordPlatformConfig};
-// use crate::workers::{poll_worker, record_worker, polling_write_worker, record_write_worker, PollingInterpretation};
-// use crate::workers::PollingInterpretation::Transition;
+use crate::observers::platform::PlatformObserver;
+use crate::observers::square::{SquareObserve, SquareObserver, SquareObserverConfig};
+use crate::observers::webhook::webhook_worker;
+use crate::observers::PlatformAdapter;
+use crate::value::{Quantity, States, Target, Value};
+use crate::workers::{poll_worker, record_worker, write_worker};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Config {
+    #[serde(default = "default_profiling_directory")]
+    pub(crate) profiling_directory: String,
+    #[serde(default)]
+    pub(crate) conflict_policy: ConflictPolicyConfig, // How levels that could not be defined are resolved.
+    #[serde(default)]
+    pub(crate) observers: Vec<(String, SquareObserverConfig)>,
+    #[serde(default = "default_idle_after")]
+    pub(crate) idle_after: u64, // Seconds a target goes unobserved before it may be evicted.
+    #[serde(default = "default_checkpoint_every")]
+    pub(crate) checkpoint_every: u64, // Seconds between journaling what targets folded into - bounds the journal, and restart.
+}
 
+fn default_profiling_directory() -> String {
+    "profile".to_string()
+}
 
-// async fn real_evaluation() {
-//     // Load configuration.
-//     let config: Config = serde_json::from_reader(File::open(Path::new("config.json")).unwrap()).unwrap();
-//     info!("MAIN - Configuration Loaded Successfully.");
-//
-//     let mut observers = HashMap::new();
-//     // Initialise Observers and Testers.
-//     let mut testing_workers = JoinSet::new();
-//
-//     for (uid, cfg) in config.observers {
-//         let new_observer = Arc::new(SquareObserver::new(uid.clone(), cfg.clone()));
-//         let local_observer_1 = new_observer.clone();
-//         let local_observer_2 = new_observer.clone();
-//         let local_config_2 = cfg.clone();
-//         testing_workers.spawn((async move || manual_editor(local_observer_1, cfg.clone(), cfg.testing_config.edit_lambda).await)());
-//         testing_workers.spawn((async move || external_users(local_observer_2, local_config_2.clone(), local_config_2.testing_config.sale_lambda).await)());
-//
-//         observers.insert(uid, new_observer);
-//     }
-//     info!("MAIN - Initialised Observers");
-//     // TODO: Deviation Calculations.
-//
-//     let mut initial_value = None;
-//     for obs in observers.values() {
-//         let (value, _, _) = obs.request(obs.target.clone()).await.unwrap();
-//         if initial_value.is_none() {
-//             initial_value.replace(value); // If no replies yet - set!
-//         } else if let Some(v) = initial_value {
-//             if v != value {
-//                 initial_value = None;
-//                 break; // Stop iterating - initial value stays none.
-//             }
-//             // Otherwise - is same as others - still might be a shared init value!
-//         }
-//     }
-//     info!("MAIN - Initial Value : {initial_value:?}");
-//
-//     // We will test polling with subset:
-//     let polling_observers = vec!["Vendor A"];
-//
-//     // We will test timestamped records with subset:
-//     let record_observers = vec!["Vendor B"];
-//
-//     // Initialise Coordinator Channels.
-//     let (obs_tx, obs_rx) = mpsc::channel(1024);
-//     let (consensus_tx, consensus_rx) = watch::channel(None);
-//
-//
-//     // Initialise Polling Workers (and writers)
-//     let mut polling_workers = JoinSet::new();
-//     let mut polling_write_workers = JoinSet::new();
-//     info!("MAIN - Initialising Polling Workers for {polling_observers:?}");
-//     for name in polling_observers {
-//         // Clones for move.
-//         let local_observer = observers.get(name).unwrap().clone();
-//         let local_obs_tx = obs_tx.clone();
-//         let local_target = local_observer.target.clone();
-//         let local_mutex = Arc::new(Mutex::new(initial_value));
-//         let local_mutex_writer = local_mutex.clone();
-//         // Initialise Worker.
-//         polling_workers.spawn((async move || poll_worker(
-//             local_observer,
-//             Transition,  // Interpret changes as Transitions.
-//             local_target,
-//             TimeDelta::seconds(1),
-//             name.to_string(),
-//             local_mutex,
-//             local_obs_tx // Send observations here.
-//         ).await)());
-//
-//         let local_observer = observers.get(name).unwrap().clone();
-//         let local_target = local_observer.target.clone();
-//         let local_consensus_rx = consensus_rx.clone();
-//
-//
-//         polling_write_workers.spawn((async move || polling_write_worker(
-//             local_observer,
-//             local_target,
-//             local_mutex_writer,
-//             local_consensus_rx
-//         ).await)());
-//     }
-//
-//     // Initialise Record Workers:
-//     let mut record_workers = JoinSet::new();
-//     let mut record_write_workers = JoinSet::new();
-//     info!("MAIN - Initialising Record Workers for {record_observers:?}");
-//     for name in record_observers {
-//         // Clone for move.
-//         let local_obs_tx = obs_tx.clone();
-//         let local_observer = observers.get(name).unwrap().clone();
-//         let local_target = local_observer.target.clone();
-//
-//         debug!("Spawning: {}", name);
-//         // Initialise Worker.
-//         record_workers.spawn((async move || record_worker(
-//             local_observer,
-//             local_target,
-//             TimeDelta::seconds(1),
-//             local_obs_tx // Send observations here.
-//         ).await)());
-//
-//         let local_observer = observers.get(name).unwrap().clone();
-//         let local_target = local_observer.target.clone();
-//         let local_consensus_rx = consensus_rx.clone();
-//
-//         record_write_workers.spawn((async move || record_write_worker(
-//             local_observer,
-//             local_target,
-//             local_consensus_rx
-//         ).await)());
-//     }
-//
-//     // Initialise Coordinator
-//     info!("MAIN - Initialising Coordinator Worker!");
-//     let coordinator_future = coordinator(initial_value, obs_rx, consensus_tx);
-//
-//
-//     // Run Threads!
-//     info!("MAIN - INITIALISED - RUNNING!");
-//     futures::join!(polling_workers.join_all(), record_workers.join_all(), polling_write_workers.join_all(), record_write_workers.join_all(), coordinator_future, testing_workers.join_all());
-// }
+fn default_idle_after() -> u64 {
+    3600
+}
+
+fn default_checkpoint_every() -> u64 {
+    60
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            profiling_directory: default_profiling_directory(),
+            conflict_policy: ConflictPolicyConfig::default(),
+            observers: Vec::new(),
+            idle_after: default_idle_after(),
+            checkpoint_every: default_checkpoint_every(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+}
+
+const CHANNEL_BUFFER: usize = 1024;
+
+// Observes every configured Square account - targets observed by several share one consensus.
+async fn live(config: Config) -> Result<(), Error> {
+    let directory = Path::new(&config.profiling_directory);
+    create_dir_all(directory)?;
+    let (send, receive) = mpsc::channel(CHANNEL_BUFFER);
+    let publisher = Publisher::new();
+    let mut initial: HashMap<Target, Counted<States<Value>>> = HashMap::new();
+    let mut expected: HashMap<Target, Vec<SourceKind>> = HashMap::new();
+    let mut workers = JoinSet::new();
+
+    for (name, cfg) in config.observers {
+        let square = SquareObserver::new(name.clone(), cfg.clone())?;
+        let target = square.target.clone();
+
+        // Counted before observing - so records fetched from then on are not already in it.
+        // Targets start unknown unless every observer of them counts alike.
+        let sent = PlatformAdapter::<States<Value>>::now(&square);
+        let count = square.count(&target).await?;
+        let replied = PlatformAdapter::<States<Value>>::now(&square);
+        initial.entry(target.clone())
+            .and_modify(|counted| {
+                if counted.value != Some(count) {
+                    counted.value = None;
+                }
+                counted.replied = replied;
+            })
+            .or_insert(Counted { value: Some(count), source: SourceKind::Polling(name.clone()), sent, replied });
+
+        let interpretation = match cfg.observe {
+            SquareObserve::Polling(interpretation) => interpretation,
+            SquareObserve::Records => PollingInterpretation::Transition, // Only polled to write.
+        };
+        let observer = PlatformObserver::new(square, interpretation)
+            .recalibrating(Duration::from_secs(cfg.recalibrate))
+            .calibrated(directory.join(format!("{name}.calibration")))?;
+        let observer = Arc::new(Mutex::new(observer));
+
+        let every = Duration::from_millis(cfg.every);
+        let source = match cfg.observe {
+            SquareObserve::Polling(_) => {
+                workers.spawn(poll_worker(observer.clone(), target.clone(), every, send.clone()));
+                SourceKind::Polling(name.clone())
+            }
+            SquareObserve::Records => {
+                workers.spawn(record_worker(observer.clone(), target.clone(), every, send.clone()));
+                SourceKind::Record(name.clone())
+            }
+        };
+        expected.entry(target.clone()).or_default().push(source);
+        workers.spawn(write_worker(observer.clone(), target.clone(), publisher.subscribe(target.clone())));
+        if let Some(webhook) = cfg.webhook {
+            expected.entry(target.clone()).or_default().push(SourceKind::Push(name.clone()));
+            let send = send.clone();
+            workers.spawn(async move {
+                if let Err(e) = webhook_worker(webhook, observer, every, send).await {
+                    error!("MAIN - Webhook stopped: {e}");
+                }
+            });
+        }
+        info!("MAIN - Observing {target:?} on {name}");
+    }
+    drop(send); // Coordinator stops once every observer has.
+    let (explainer, queries) = Explainer::new();
+    tokio::spawn(explain_unknown(explainer, publisher.subscribe_all()));
+
+    let journal = Journal::open(directory.join("journal.log"))?;
+    let policy = config.conflict_policy;
+    let idle_after = Duration::from_secs(config.idle_after);
+    let checkpoint_every = Duration::from_secs(config.checkpoint_every);
+    info!("MAIN - Initial Values: {initial:?}");
+    coordinator(|| NewHistory::new(policy.build()), initial, expected, idle_after, checkpoint_every, journal, receive, queries, publisher).await
+}
+
+// Logs why a target's consensus became unknown - for whoever has to set it right.
+async fn explain_unknown<V: Quantity>(explainer: Explainer<V>, mut published: broadcast::Receiver<(Target, Option<V>)>) {
+    loop {
+        match published.recv().await {
+            Ok((target, None)) => {
+                if let Some(explanation) = explainer.explain(target.clone()).await {
+                    warn!("MAIN - {target:?} unknown: {}", serde_json::to_string(&explanation).unwrap_or_default());
+                }
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
 
 // #[derive(Debug)]
 // pub struct MockTestingConfig {
@@ -217,156 +233,143 @@ use crate::observers::mocked::record_platform::{MockRecordPlatform, MockRecordPl
 //     info!("{}", global_observations.iter().map(|x| x.pretty_output(&from)).collect::<Vec<String>>().join("\n"));
 // }
 // // const CHANNEL_BUFFER: usize = 100;
-fn simulate(until: Tick) {
-    let mut time: Tick = 0; // Simulated RealTime.
-
+// Observes mocked platforms through the same observers, workers and coordinator as live - in paused time.
+// Each millisecond the published consensus is checked against what every change the platforms made leads to.
+async fn simulate<H: Inference>(config: Config, until: Duration, new_history: impl Fn() -> H) -> Result<(), Error> {
+    let directory = Path::new(&config.profiling_directory);
+    create_dir_all(directory)?;
+    let target: Target = ("Simulated".to_string(), "Item".to_string()); // Mocked platforms hold just the one.
     let initial_value = 10000;
+    let (rtt, every) = (Duration::from_millis(40), Duration::from_secs(1));
 
-    let mut test_polling_platform = MockPlatform::new(MockPlatformConfig {
+    let polling_platform = Arc::new(LivePlatform::new(MockPlatform::new(MockPlatformConfig {
         name: "PollPlatform".to_string(),
         sale_lambda: 0.00003,
         edit_lambda: 0.0,
-    }, 10000);
-
-    let mut test_poller = MockPoller::new(
-        40.0,
-        1.0,
-        1000,
-        PollingInterpretation::Mutation
-    );
-
-    // Test Writer
-    let mut test_poll_writer = InstantWriter::new();
-
-
-    let mut test_record_platform = MockRecordPlatform::new(MockRecordPlatformConfig {
+    }, initial_value), rtt));
+    let record_platform = Arc::new(LivePlatform::new(MockRecordPlatform::new(MockRecordPlatformConfig {
         name: "RecordPlatform".to_string(),
         sale_lambda: 0.00003,
         edit_lambda: 0.0,
         deviation_lambda: 500.0,
         deviation_std_dev: 0.2,
         clock_precision: 1,
-    }, 10000);
-
-    let mut test_record_poller = MockRecordPoller::new(
-        40.0,
-        1.0,
-        1000,
-        1
-    );
-
-    // TODO: Record Pollers don't need writers - underlying value is irrelevant.
-    // We assume that a value gets written, and that we can identify and ignore actions originating from us.
-    // therefore - event stream is unchanged by writes, and thus derived value is unchanged.
-
-    let mut true_history = Vec::new();
-    let mut observed_history = NewHistory::new();
-
-
-
-    let mut consistent = true;
-    let mut conflict = false;
-    let mut time_to_convergence = 0;
-    let mut convergence_times = Vec::new();
-
-    let mut observed_value = Some(initial_value);
-    let mut true_value = initial_value;
-
-    while time <= until {
-        let mut new_event = false;
-        let mut new_observation = false;
-       if let Some(event) = test_polling_platform.do_tick(&time) {
-           // debug!("Simulator - Event: {event:?} at {time}");
-           true_history.push(event);
-           new_event = true;
-       }
-
-       if let Some(event) = test_record_platform.do_tick(&time) {
-           // debug!("Simulator - Event: {event:?} at {time}");
-           true_history.push(event);
-           new_event = true;
-       }
-
-       if let Some(obs) = test_poller.do_tick(&time, &test_polling_platform) {
-           // info!("Simulator - {obs:?} at {time}");
-           observed_history.add_new(obs);
-
-           new_observation = true;
-       }
-
-       if let Some(obs) = test_record_poller.do_tick(&time, &mut test_record_platform) {
-           // debug!("Simulator - {obs:?} at {time}");
-           for o in obs {
-               observed_history.add_new(o);
-           }
-           new_observation = true;
-       }
-
-        if new_event {
-            true_value = initial_value;
-            for (event, _) in &true_history {
-                true_value = event.apply(&true_value).unwrap();
-            }
-        }
-
-        if new_observation {
-            observed_value = observed_history.apply(Some(initial_value));
-            // TODO: Writing Values :)
-            // We consider both Instantaneous (conflict free writes)
-            if let Some(new_value) = observed_value {
-                // info!("InstantWriter - Wrote: {new_value:?} at {time}");
-                test_poll_writer.do_write(new_value, &mut test_poller, &time);
+    }, initial_value), rtt));
+
+    let (send, receive) = mpsc::channel(CHANNEL_BUFFER);
+    let publisher = Publisher::new();
+    let mut workers = JoinSet::new();
+    let poller = Arc::new(Mutex::new(PlatformObserver::new(polling_platform.clone(), PollingInterpretation::Mutation)));
+    let recorder = PlatformObserver::new(record_platform.clone(), PollingInterpretation::Transition).recalibrating(every);
+    let recorder = Arc::new(Mutex::new(recorder));
+    workers.spawn(poll_worker(poller.clone(), target.clone(), every, send.clone()));
+    workers.spawn(record_worker(recorder.clone(), target.clone(), every, send));
+    workers.spawn(write_worker(poller, target.clone(), publisher.subscribe(target.clone())));
+    workers.spawn(write_worker(recorder, target.clone(), publisher.subscribe(target.clone())));
+
+    let journal = directory.join("simulation.log");
+    if journal.exists() {
+        remove_file(&journal)?; // Every simulation starts afresh.
+    }
+    let journal = Journal::open(journal)?;
+    let idle_after = Duration::from_secs(config.idle_after);
+    let checkpoint_every = Duration::from_secs(config.checkpoint_every);
+    let expected = vec![SourceKind::Polling(polling_platform.name().to_string()), SourceKind::Record(record_platform.name().to_string())];
+    let counted = polling_platform.now();
+    let initial = HashMap::from([(target.clone(), Counted {
+        value: Some(initial_value), source: SourceKind::Polling(polling_platform.name().to_string()), sent: counted, replied: counted
+    })]);
+    let (explainer, queries) = Explainer::new();
+    let coordinated = coordinator(new_history, initial, HashMap::from([(target.clone(), expected)]), idle_after, checkpoint_every, journal, receive, queries, publisher.clone());
+
+    let mut consensus = publisher.subscribe(target.clone());
+    let checked = async move {
+        let started = Instant::now();
+        let mut published = false; // Nothing is published until observed - the initial value stands until then.
+        let mut diverged: Option<Instant> = None;
+        let mut convergence_times = Vec::new();
+        let mut conflict = None;
+        let mut mark = 1;
+        while started.elapsed() < until {
+            sleep(Duration::from_millis(1)).await;
+            published |= consensus.has_changed().unwrap_or(true);
+            let observed = if published { *consensus.borrow_and_update() } else { Some(initial_value) };
+            let Some(observed) = observed else {
+                info!("Simulator - Conflict, ending simulation.");
+                if let Some(explanation) = explainer.explain(target.clone()).await {
+                    info!("Simulator - Explanation: {}", serde_json::to_string(&explanation).unwrap_or_default());
+                }
+                conflict = Some(started.elapsed());
+                break;
+            };
+            let true_value = polling_platform.happened().into_iter().chain(record_platform.happened())
+                .fold(initial_value, |value, (event, _)| event.apply(&value).unwrap());
+            match (observed == true_value, diverged) {
+                (false, None) => diverged = Some(Instant::now()),
+                (true, Some(since)) => {
+                    convergence_times.push(since.elapsed().as_millis() as u64);
+                    diverged = None;
+                }
+                _ => {}
             }
-            // AND potentially lossy-writes (realistic, no mutex)
-
-        }
-
-        // Do Writer Tick
-        test_poll_writer.do_tick(&mut test_polling_platform, &mut test_poller, &time);
-
-        if observed_value.is_none() {
-            info!("Simulator - Conflict, ending simulation.");
-            conflict = true;
-            break;
-        }
-
-        if observed_value.unwrap() != true_value {
-            consistent = false;
-        }
-
-        if !consistent {
-            if observed_value.unwrap() == true_value {
-                consistent = true;
-                convergence_times.push(time_to_convergence);
-                time_to_convergence = 0;
-            } else {
-                time_to_convergence += 1;
+            if started.elapsed() >= until * mark / 10 {
+                info!("{}0% MARK", mark);
+                mark += 1;
             }
         }
+        workers.shutdown().await; // Coordinator stops once every observer has.
+        (convergence_times, conflict)
+    };
 
-        if time % (until/10) == 0 {
-            info!("10% MARK")
-        }
-
-        time += 1;
-    }
+    let (coordinated, (convergence_times, conflict)) = tokio::join!(coordinated, checked);
+    coordinated?;
     info!("Simulation Complete!");
     info!("Convergence Times: {:?}", convergence_times);
-    info!("Average Time To Convergence: {}", convergence_times.iter().sum::<u64>() / convergence_times.len() as u64);
-    if conflict {
-        info!("Time to Conflict: {}", time);
-    } else {
-        info!("No Conflicts!")
+    if let Some(average) = convergence_times.iter().sum::<u64>().checked_div(convergence_times.len() as u64) {
+        info!("Average Time To Convergence: {average}");
     }
-
+    match conflict {
+        Some(after) => info!("Time to Conflict: {}", after.as_millis()),
+        None => info!("No Conflicts!")
+    }
+    Ok(())
 }
 
 
-#[tokio::main]
-async fn main() {
+fn main() {
     colog::init();
-    info!("MAIN - Starting Simulation");
-    simulate(600000);
+    // synchronaive [simulate|live] [config]
+    let mode = std::env::args().nth(1).unwrap_or("simulate".to_string());
+    let path = std::env::args().nth(2).unwrap_or("config.json".to_string());
+    let config = match Config::load(Path::new(&path)) {
+        Ok(config) => config,
+        // A simulation needs nothing configured - only live observation does.
+        Err(Error::Config(ConfigError::Io(e))) if mode == "simulate" && e.kind() == ErrorKind::NotFound => {
+            info!("MAIN - No configuration at {path}, simulating with defaults.");
+            Config::default()
+        }
+        Err(e) => return error!("MAIN - Could not load configuration from {path}: {e}"),
+    };
+    info!("MAIN - Configuration Loaded Successfully.");
+    match mode.as_str() {
+        "live" => {
+            info!("MAIN - Starting Live Observation");
+            let runtime = runtime::Builder::new_multi_thread().enable_all().build().expect("Failed to start runtime");
+            if let Err(e) = runtime.block_on(live(config)) {
+                error!("MAIN - Stopped: {e}");
+            }
+        }
+        "simulate" => {
+            info!("MAIN - Starting Simulation");
+            // Time stands still while every task waits - jumping ahead to whatever is due next.
+            let runtime = runtime::Builder::new_current_thread().enable_all().start_paused(true).build().expect("Failed to start runtime");
+            let policy = config.conflict_policy.clone();
+            if let Err(e) = runtime.block_on(simulate(config, Duration::from_secs(600), || NewHistory::new(policy.build()))) {
+                error!("MAIN - Simulation stopped: {e}");
+            }
+        }
+        _ => error!("MAIN - Unknown mode {mode} - expected simulate or live"),
+    }
     // fake_evaluation(
     //     Utc::now(),
     //     (Utc::now() + TimeDelta::seconds(60)),
@@ -387,7 +390,4 @@ async fn main() {
     //         }
     //     ]
     // ).await;
-    // info!("MAIN - Starting Real Evaluation");
-    // real_evaluation().await;
-    //
 }
\ No newline at end of file
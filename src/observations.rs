@@ -1,50 +1,105 @@
 use std::cmp::Ordering;
-use crate::inference::interval::{Interval, GT, LT, OVERLAP};
+use serde::{Deserialize, Serialize};
+use crate::inference::interval::{Deviation, Interval, Moment, Relation, TimeDistribution};
 use crate::observations::SourceKind::Polling;
-use crate::value::Value;
+use crate::value::{Quantity, Stock, Value};
 
-#[derive(Debug, Clone, Copy)]
-pub enum DefinitionPredicate {
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum DefinitionPredicate<V = Value> {
     Transition {
-        v_0: Value,
-        v_1: Value,
+        v_0: V,
+        v_1: V,
     },
     Mutation {
-        delta: Value
+        delta: V
     },
     Assignment {
-        v_new: Value
+        v_new: V
+    },
+    Count {
+        stock: Stock,
+        v_new: V // Only stock is read from it.
     }
 }
 
-impl DefinitionPredicate {
-    pub(crate) fn apply(&self, input: &Value) -> Option<Value>{
+impl<V: Quantity> DefinitionPredicate<V> {
+    pub(crate) fn apply(&self, input: &V) -> Option<V>{
         match self {
             DefinitionPredicate::Transition { v_0, v_1 } => if input == v_0 { Some(v_1.clone()) } else { None },
-            DefinitionPredicate::Mutation { delta } => {Some(input + delta)}
+            DefinitionPredicate::Mutation { delta } => input.combine(*delta), // None past the domain's range - no order gets there.
             DefinitionPredicate::Assignment { v_new } => {Some(v_new.clone())}
+            DefinitionPredicate::Count { stock, v_new } => {Some(input.counted(*stock, *v_new))}
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum SourceKind {
     Polling(String),
-    Record(String)
+    Record(String),
+    Push(String) // Delivered by the platform as it happens - a webhook.
+}
+
+// Stable across refetches - the same change seen twice carries the same ID.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ObservationId {
+    Change(SourceKind, String), // Platform's own change ID.
+    Sequence(SourceKind, u64), // Nth observation made by a source.
+    Poll(SourceKind, Moment), // Sent at this moment of ours - a target's polls are never sent together, in this run or any other.
 }
 
-#[derive(Debug, Clone)]
-pub struct Observation {
-    pub definition: DefinitionPredicate,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Observation<V = Value> {
+    pub id: ObservationId,
+    pub definition: DefinitionPredicate<V>,
     pub interval: Interval,
-    pub source: SourceKind
+    pub source: SourceKind,
+    #[serde(default)]
+    pub compensates: Option<ObservationId>, // Earlier observation this one offsets - a refund of a sale.
+    #[serde(default)]
+    pub distribution: TimeDistribution, // Of when within interval the change happened.
+    #[serde(default)]
+    pub reported: Option<Moment>, // Reply which reported it arrived - the change happened no later.
+    #[serde(default)]
+    pub timestamp: Option<Moment>, // Platform's own clock reading for the change - retimed as its deviation is revised.
+}
+
+// Withdraws an earlier observation - a voided order, or an adjustment deleted as erroneous.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Retraction {
+    pub id: ObservationId,
+    pub retracts: ObservationId,
+    pub source: SourceKind,
+    pub reason: String,
+}
+
+// What observers report to the coordinator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Report<V = Value> {
+    Observed(Observation<V>),
+    Retracted(Retraction),
+    Deviation(SourceKind, Deviation), // Revised estimate of a source's clock - retimes what it stamped.
+    Horizon(SourceKind, Moment), // Source will deliver nothing more starting before this - lets history fold.
 }
-impl Observation {
+
+impl<V> From<Observation<V>> for Report<V> {
+    fn from(value: Observation<V>) -> Self {
+        Report::Observed(value)
+    }
+}
+
+impl<V> From<Retraction> for Report<V> {
+    fn from(value: Retraction) -> Self {
+        Report::Retracted(value)
+    }
+}
+impl<V: Quantity> Observation<V> {
     pub(crate) fn pretty_output(&self) -> String {
         let label = match self.definition {
-            DefinitionPredicate::Transition { v_0, v_1 } => {format!("TR ({} -> {})", v_0, v_1)},
-            DefinitionPredicate::Mutation { delta } => {format!("MU ({delta})")},
-            DefinitionPredicate::Assignment { v_new } => {format!("AS ({v_new})")},
+            DefinitionPredicate::Transition { v_0, v_1 } => {format!("TR ({:?} -> {:?})", v_0, v_1)},
+            DefinitionPredicate::Mutation { delta } => {format!("MU ({delta:?})")},
+            DefinitionPredicate::Assignment { v_new } => {format!("AS ({v_new:?})")},
+            DefinitionPredicate::Count { stock, v_new } => {format!("CO ({stock:?} of {v_new:?})")},
         };
 
         format!(
@@ -55,34 +110,21 @@ impl Observation {
         )
     }
 }
-impl PartialEq<Self> for Observation {
+impl<V> PartialEq<Self> for Observation<V> {
     fn eq(&self, other: &Self) -> bool {
-        return false; // No two observations are equal!
-        // let mut ordered = false;
-        //
-        // // If intervals do not overlap - they are ordered.
-        // if !OVERLAP(self.interval, other.interval) {
-        //     ordered = true;
-        // }
-        //
-        // // If they are from the same platform (and it is polling) they are ordered.
-        // if let (Polling(name_1), Polling(name_2)) = (&self.source, &other.source) {
-        //     if name_1 == name_2 {
-        //         ordered = true;
-        //     }
-        // }
-        //
-        // return !ordered;
+        self.id == other.id // Same change, however many times it was observed.
     }
 }
 
-impl PartialOrd for Observation {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if LT(self.interval, other.interval) {
-            return Some(Ordering::Less);
-        }
-        if GT(self.interval, other.interval) {
-            return Some(Ordering::Greater);
+// Not PartialOrd - observations are equal by ID, while their changes are ordered by interval.
+impl<V> Observation<V> {
+    // Order in which two changes certainly happened - None if either could have come first.
+    pub(crate) fn ordering(&self, other: &Self) -> Option<Ordering> {
+        // Only intervals sharing no moment order their changes - meeting intervals may coincide.
+        match self.interval.relation(&other.interval) {
+            Relation::Before => return Some(Ordering::Less),
+            Relation::After => return Some(Ordering::Greater),
+            _ => {}
         }
         if let (Polling(name_1), Polling(name_2)) = (&self.source, &other.source) {
             if name_1 == name_2 {
@@ -93,7 +135,7 @@ impl PartialOrd for Observation {
                     return Some(Ordering::Greater);
                 }
 
-                panic!("Found Overlapping Polls!");
+                return None; // Overlapping Polls! Histories reject these before comparing.
             }
         }
 
@@ -103,6 +145,7 @@ impl PartialOrd for Observation {
 
 
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum PollingInterpretation {
     Transition,
     Assignment,
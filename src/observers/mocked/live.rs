@@ -0,0 +1,136 @@
+use std::cmp::max;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use crate::error::Error;
+use crate::inference::interval::Moment;
+use crate::observations::Tick;
+use crate::observers::{Change, PlatformAdapter};
+use crate::observers::mocked::poll_platform::MockPlatform;
+use crate::observers::mocked::record_platform::MockRecordPlatform;
+use crate::testing::Event;
+use crate::value::{Target, Value};
+
+// Mocked platforms which run on their own, tick by tick - returning any change they made.
+pub trait Ticked: Send {
+    fn name(&self) -> &str;
+    fn tick(&mut self, now: &Tick) -> Option<Event>;
+}
+
+impl Ticked for MockPlatform {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn tick(&mut self, now: &Tick) -> Option<Event> {
+        self.do_tick(now)
+    }
+}
+
+impl Ticked for MockRecordPlatform {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn tick(&mut self, now: &Tick) -> Option<Event> {
+        self.do_tick(now)
+    }
+}
+
+// A mocked platform reached in real time, as if over the network - a tick per millisecond since it started.
+// Each request takes a round trip, and is processed halfway through it.
+// Mocked platforms hold a single target - whichever target is asked for, it is that one.
+pub struct LivePlatform<P> {
+    name: String,
+    platform: Mutex<(P, Tick, Vec<Event>)>, // With the tick it has run until, and every change it made on its own.
+    started: Instant,
+    rtt: Duration,
+}
+
+impl<P: Ticked> LivePlatform<P> {
+    pub fn new(platform: P, rtt: Duration) -> Self {
+        LivePlatform { name: platform.name().to_string(), platform: Mutex::new((platform, 0, Vec::new())), started: Instant::now(), rtt }
+    }
+
+    fn tick(&self) -> Tick {
+        self.started.elapsed().as_millis() as Tick
+    }
+
+    // The platform, run up to now.
+    fn run(&self) -> (MutexGuard<'_, (P, Tick, Vec<Event>)>, Tick) {
+        let mut guard = self.platform.lock().unwrap();
+        let (platform, until, happened) = &mut *guard;
+        let now = self.tick();
+        for tick in *until + 1..=now {
+            happened.extend(platform.tick(&tick));
+        }
+        *until = max(*until, now);
+        (guard, now)
+    }
+
+    // Every change the platform made on its own so far - what observing it should account for.
+    pub fn happened(&self) -> Vec<Event> {
+        self.run().0.2.clone()
+    }
+
+    // Runs the platform up to when the request is processed, then processes it.
+    async fn request<T>(&self, process: impl FnOnce(&mut P, Tick) -> T + Send) -> T {
+        sleep(self.rtt / 2).await;
+        let result = {
+            let (mut guard, now) = self.run();
+            process(&mut guard.0, now)
+        };
+        sleep(self.rtt / 2).await;
+        result
+    }
+}
+
+impl PlatformAdapter for LivePlatform<MockPlatform> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn now(&self) -> Moment {
+        Moment(self.tick())
+    }
+
+    async fn count(&self, _target: &Target) -> Result<Value, Error> {
+        Ok(self.request(|platform, _| platform.value).await)
+    }
+
+    async fn write(&self, _target: &Target, value: Value) -> Result<(), Error> {
+        self.request(move |platform, _| platform.value = value).await;
+        Ok(())
+    }
+}
+
+impl PlatformAdapter for LivePlatform<MockRecordPlatform> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn now(&self) -> Moment {
+        Moment(self.tick())
+    }
+
+    async fn count(&self, _target: &Target) -> Result<Value, Error> {
+        Ok(self.request(|platform, _| platform.value).await)
+    }
+
+    async fn write(&self, _target: &Target, value: Value) -> Result<(), Error> {
+        self.request(move |platform, _| platform.value = value).await;
+        Ok(())
+    }
+
+    async fn changes(&self, _target: &Target, since: Moment) -> Result<Vec<Change>, Error> {
+        Ok(self.request(move |platform, _| platform.events.iter()
+            .filter(|(_, _, timestamp)| Moment(*timestamp) >= since)
+            .map(|(id, definition, timestamp)| (id.clone(), *definition, Moment(*timestamp)))
+            .collect()
+        ).await)
+    }
+
+    async fn clock(&self) -> Result<Moment, Error> {
+        Ok(self.request(|platform, now| Moment(platform.get_deviating_clock(&now))).await)
+    }
+}
@@ -1,5 +1,3 @@
 pub mod poll_platform;
-pub mod polling;
-pub mod record;
 pub mod record_platform;
-pub mod mock_writer;
\ No newline at end of file
+pub mod live;
\ No newline at end of file
@@ -1,5 +1,6 @@
-use rand::prelude::ThreadRng;
 use rand::rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use crate::observations::Tick;
 use crate::observations::DefinitionPredicate;
 use crate::observations::DefinitionPredicate::Mutation;
@@ -16,12 +17,12 @@ pub struct MockPlatform {
     pub(crate) value: Value,
     pub(crate) config: MockPlatformConfig,
     next_sale: Tick,
-    rng: ThreadRng
+    rng: StdRng // Sendable - a live platform is shared between tasks.
 }
 
 impl MockPlatform {
     pub(crate) fn new(config: MockPlatformConfig, initial_value: Value) -> Self {
-        let mut rng = rng();
+        let mut rng = StdRng::from_rng(&mut rng());
         let next_sale = exp(config.sale_lambda, &mut rng);;
 
         MockPlatform {
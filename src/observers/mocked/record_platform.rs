@@ -1,5 +1,6 @@
-use rand::prelude::ThreadRng;
 use rand::rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use crate::observations::Tick;
 use crate::observations::DefinitionPredicate;
 use crate::observations::DefinitionPredicate::Mutation;
@@ -15,23 +16,27 @@ pub struct MockRecordPlatformConfig {
     pub(crate) clock_precision: Tick // In significant Figures?
 }
 
+// Change ID, change and the platform's timestamp of it.
+pub type Record = (String, DefinitionPredicate, Tick);
+
 pub struct MockRecordPlatform {
     pub(crate) value: Value,
-    pub(crate) events: Vec<(DefinitionPredicate, Tick)>,
+    pub(crate) events: Vec<Record>,
     pub(crate) config: MockRecordPlatformConfig,
     next_sale: Tick,
-    rng: ThreadRng
+    next_change: u64,
+    rng: StdRng // Sendable - a live platform is shared between tasks.
 }
 
 impl MockRecordPlatform {
     pub(crate) fn new(config: MockRecordPlatformConfig, initial_value: Value) -> Self {
-        let mut rng = rng();
+        let mut rng = StdRng::from_rng(&mut rng());
         let next_sale = exp(config.sale_lambda, &mut rng);;
 
         MockRecordPlatform {
             value: initial_value,
             events: Vec::new(),
-            config, next_sale, rng
+            config, next_sale, next_change: 0, rng
         }
     }
 
@@ -49,7 +54,8 @@ impl MockRecordPlatform {
             self.value = event.0.apply(&self.value).unwrap();
 
             let deviating_clock = self.get_deviating_clock(now);
-            self.events.push((event.0.clone(), deviating_clock));
+            self.events.push((format!("{}-{}", self.config.name, self.next_change), event.0.clone(), deviating_clock));
+            self.next_change += 1;
 
             self.next_sale = now + exp(self.config.sale_lambda, &mut self.rng);
             return Some(event);
@@ -1,2 +1,80 @@
+use std::future::Future;
+use std::sync::Arc;
+use crate::error::{Error, ObserverError};
+use crate::inference::interval::Moment;
+use crate::observations::{DefinitionPredicate, Report};
+use crate::value::{Quantity, Target, Value};
+
 pub mod mocked;
-pub mod square;
\ No newline at end of file
+pub mod platform;
+pub mod square;
+pub mod webhook;
+
+// A change as a platform recorded it - its own ID for it, and its own clock's timestamp of it.
+pub type Change<V = Value> = (String, DefinitionPredicate<V>, Moment);
+
+// What a platform offers, however it is reached - simulated or over the network.
+// Every platform can be polled and written - records and a readable clock are optional.
+pub trait PlatformAdapter<V: Quantity = Value>: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Our clock - observations of this platform are timed by it.
+    fn now(&self) -> Moment;
+
+    // Target's current value.
+    fn count(&self, target: &Target) -> impl Future<Output = Result<V, Error>> + Send;
+
+    // Sets target's value - not itself recorded as a change.
+    fn write(&self, target: &Target, value: V) -> impl Future<Output = Result<(), Error>> + Send;
+
+    // Changes to target the platform stamped at or after since, by its own clock.
+    fn changes(&self, _target: &Target, _since: Moment) -> impl Future<Output = Result<Vec<Change<V>>, Error>> + Send {
+        async { Err(ObserverError::Unsupported("records").into()) }
+    }
+
+    // Platform's own clock - as it would stamp a change made now.
+    fn clock(&self) -> impl Future<Output = Result<Moment, Error>> + Send {
+        async { Err(ObserverError::Unsupported("clock").into()) }
+    }
+}
+
+// A platform shared with whatever else reaches it - a simulation checks what was observed against it.
+impl<V: Quantity, P: PlatformAdapter<V>> PlatformAdapter<V> for Arc<P> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn now(&self) -> Moment {
+        (**self).now()
+    }
+
+    fn count(&self, target: &Target) -> impl Future<Output = Result<V, Error>> + Send {
+        (**self).count(target)
+    }
+
+    fn write(&self, target: &Target, value: V) -> impl Future<Output = Result<(), Error>> + Send {
+        (**self).write(target, value)
+    }
+
+    fn changes(&self, target: &Target, since: Moment) -> impl Future<Output = Result<Vec<Change<V>>, Error>> + Send {
+        (**self).changes(target, since)
+    }
+
+    fn clock(&self) -> impl Future<Output = Result<Moment, Error>> + Send {
+        (**self).clock()
+    }
+}
+
+// Turns what a platform reports into observations of its targets.
+pub trait Observer<V: Quantity = Value>: Send {
+    // Polls target - an observation if its value changed since the last poll (or write),
+    // then the horizon no later poll's observation can start before.
+    fn poll(&mut self, target: &Target) -> impl Future<Output = Result<Vec<Report<V>>, Error>> + Send;
+
+    // Fetches changes recorded since the last fetch - with any revision of the platform clock's deviation,
+    // then the horizon no change still to be fetched can start before.
+    fn fetch(&mut self, target: &Target) -> impl Future<Output = Result<Vec<Report<V>>, Error>> + Send;
+
+    // Writes a consensus value - later polls compare against it, rather than observe it as a change.
+    fn write(&mut self, target: &Target, value: V) -> impl Future<Output = Result<(), Error>> + Send;
+}
@@ -0,0 +1,339 @@
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
+use std::fs::{read, rename, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
+use crate::error::{Error, ParseError};
+use crate::inference::interval::{Deviation, Interval, Moment, TimeDistribution};
+use crate::observations::{DefinitionPredicate, Observation, ObservationId, PollingInterpretation, Report, SourceKind};
+use crate::observers::{Observer, PlatformAdapter};
+use crate::value::{Quantity, Target};
+
+// Observes a platform through its adapter - alike whether the platform is simulated or real.
+pub struct PlatformObserver<V, P> {
+    platform: P,
+    interpretation: PollingInterpretation,
+    started: Moment,
+    polled: HashMap<Target, (V, Moment)>, // Last value polled or written per target, and when it was sent.
+    shared: watch::Sender<Shared<V>>, // Published for whoever receives the platform's pushes.
+    fetched: HashMap<Target, (Moment, HashSet<String>)>, // Latest platform timestamp fetched per target, and the changes at it - the next fetch starts there.
+    deviation: Option<Deviation>, // Of the platform's clock from ours - None until first probed.
+    probed: Option<Moment>, // When the deviation was last probed - None until probed in this run.
+    recalibrate: Option<Duration>, // How often the deviation is probed again - clocks drift.
+    kept: Option<watch::Sender<Option<Calibration>>>, // Keeps each revised estimate between runs - off the observer.
+    announced: HashMap<Target, Deviation>, // Estimate each target's history was last told of.
+    registered: HashSet<Target>, // Targets whose history knows the record horizon.
+}
+
+// What receivers of a platform's pushes need of its observer - read as it is revised, without waiting on the observer.
+#[derive(Debug, Clone)]
+pub struct Shared<V> {
+    pub deviation: Option<Deviation>, // Times what the platform stamps.
+    pub written: HashMap<Target, Written<V>>, // Last write per target - platforms pushing every change push it back.
+}
+
+// Value written to a target, sent and replied to at these moments of ours.
+#[derive(Debug, Clone, Copy)]
+pub struct Written<V> {
+    pub value: V,
+    pub sent: Moment,
+    pub replied: Moment,
+}
+
+const DRIFT_PPM: u64 = 100; // How far a clock may drift, in millionths of the time elapsed - a generous crystal's.
+
+// Estimate of a platform clock's deviation, as kept between runs.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Calibration {
+    deviation: Deviation,
+    probed: Moment,
+}
+
+impl<V: Quantity, P: PlatformAdapter<V>> PlatformObserver<V, P> {
+    pub fn new(platform: P, interpretation: PollingInterpretation) -> Self {
+        let started = platform.now();
+        PlatformObserver {
+            platform, interpretation, started, polled: HashMap::new(), fetched: HashMap::new(),
+            shared: watch::channel(Shared { deviation: None, written: HashMap::new() }).0,
+            deviation: None, probed: None, recalibrate: None, kept: None, announced: HashMap::new(),
+            registered: HashSet::new()
+        }
+    }
+
+    pub fn recalibrating(mut self, every: Duration) -> Self {
+        self.recalibrate = Some(every);
+        self
+    }
+
+    // Keeps the estimate at path - resuming from any kept there before.
+    // A resumed estimate is widened by what the clock may have drifted since, and probed again before it is narrowed.
+    pub fn calibrated(mut self, path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        if path.exists() {
+            let calibration: Calibration = serde_json::from_slice(&read(&path)?)?;
+            info!("{} - Resuming from {:?} probed at {:?}", self.platform.name(), calibration.deviation, calibration.probed);
+            self.deviation = Some(drifted(calibration.deviation, calibration.probed, self.started));
+            self.shared.send_modify(|shared| shared.deviation = self.deviation);
+        }
+        let (kept, calibrations) = watch::channel(None);
+        tokio::spawn(keep(path, calibrations));
+        self.kept = Some(kept);
+        Ok(self)
+    }
+
+    pub fn platform(&self) -> &P {
+        &self.platform
+    }
+
+    pub fn share(&self) -> watch::Receiver<Shared<V>> {
+        self.shared.subscribe()
+    }
+
+    // Reads the platform's clock between two readings of ours - its deviation is within what the round trip allows.
+    // Each probe narrows the estimate, widened first by what the clock may have drifted since it was last probed -
+    // one disagreeing with it even so (the clock was stepped) replaces it.
+    // Returns the estimate if it was revised.
+    pub async fn probe(&mut self) -> Result<Option<Deviation>, Error> {
+        let sent = self.platform.now();
+        let reading = self.platform.clock().await?;
+        let replied = self.platform.now();
+        let probed = Deviation { min: reading.0 as i64 - replied.0 as i64, max: reading.0 as i64 - sent.0 as i64 };
+
+        // Resumed estimates were widened up to when we started.
+        let known = self.deviation.map(|known| drifted(known, self.probed.unwrap_or(self.started), sent));
+        let revised = match known {
+            Some(known) if known.min <= probed.max && probed.min <= known.max => {
+                Deviation { min: max(known.min, probed.min), max: min(known.max, probed.max) }
+            }
+            Some(known) => {
+                info!("{} - Clock moved from {known:?} to {probed:?}", self.platform.name());
+                probed
+            }
+            None => probed,
+        };
+        self.probed = Some(replied);
+        let changed = self.deviation != Some(revised);
+        self.deviation = Some(revised);
+        self.shared.send_modify(|shared| shared.deviation = Some(revised));
+        if let Some(kept) = &self.kept {
+            kept.send_replace(Some(Calibration { deviation: revised, probed: replied }));
+        }
+        Ok(changed.then_some(revised))
+    }
+
+    // Probed first before anything can be timed - even if resumed - then again as often as asked.
+    fn calibration_due(&self) -> bool {
+        match (self.probed, self.recalibrate) {
+            (None, _) => true,
+            (Some(probed), Some(every)) => self.platform.now().0.saturating_sub(probed.0) >= every.as_millis() as u64,
+            (Some(_), None) => false,
+        }
+    }
+}
+
+// Estimate as wide as the clock may have drifted to since probed.
+fn drifted(deviation: Deviation, probed: Moment, now: Moment) -> Deviation {
+    let allowance = (now.0.saturating_sub(probed.0).saturating_mul(DRIFT_PPM)).div_ceil(1_000_000) as i64;
+    Deviation { min: deviation.min.saturating_sub(allowance), max: deviation.max.saturating_add(allowance) }
+}
+
+// Keeps the latest estimate at path until the observer is dropped - estimates revised while one is written are skipped.
+async fn keep(path: PathBuf, mut calibrations: watch::Receiver<Option<Calibration>>) {
+    while calibrations.changed().await.is_ok() {
+        let Some(calibration) = *calibrations.borrow_and_update() else { continue };
+        let path = path.clone();
+        match spawn_blocking(move || persist(&path, &calibration)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Calibration - Failed to keep {calibration:?}: {e}"),
+            Err(e) => error!("Calibration - Failed to keep {calibration:?}: {e}"),
+        }
+    }
+}
+
+// Written aside and synced first, so a crash keeps the last whole estimate - the rename is durable once the directory is synced.
+fn persist(path: &Path, calibration: &Calibration) -> Result<(), Error> {
+    let aside = path.with_extension("calibrating");
+    let mut file = File::create(&aside)?;
+    file.write_all(&serde_json::to_vec(calibration)?)?;
+    file.sync_all()?;
+    rename(&aside, path)?;
+    let directory = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+impl<V: Quantity, P: PlatformAdapter<V>> Observer<V> for PlatformObserver<V, P> {
+    // A later poll's observation opens at this poll's send - or at a write's, which is later still.
+    async fn poll(&mut self, target: &Target) -> Result<Vec<Report<V>>, Error> {
+        let source = SourceKind::Polling(self.platform.name().to_string());
+        let sent = self.platform.now();
+        let value = self.platform.count(target).await?;
+        let replied = self.platform.now();
+        let horizon = Report::Horizon(source.clone(), sent);
+
+        let Some((last, last_sent)) = self.polled.insert(target.clone(), (value, sent)) else {
+            return Ok(vec![horizon]); // First poll - nothing to compare against.
+        };
+        if last == value {
+            return Ok(vec![horizon]);
+        }
+        let definition = match self.interpretation {
+            PollingInterpretation::Mutation => {
+                let delta = value.difference(last).ok_or_else(|| ParseError::Overflow(format!("{value:?} less {last:?}")))?;
+                DefinitionPredicate::Mutation { delta }
+            }
+            PollingInterpretation::Assignment => DefinitionPredicate::Assignment { v_new: value },
+            PollingInterpretation::Transition => DefinitionPredicate::Transition { v_0: last, v_1: value },
+        };
+        let observation = Observation {
+            id: ObservationId::Poll(source.clone(), sent),
+            definition,
+            interval: Interval(last_sent, replied),
+            source,
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: Some(replied),
+            timestamp: None,
+        };
+        Ok(vec![Report::Observed(observation), horizon])
+    }
+
+    // Records are not fetched until the clock has been probed - they could not be timed.
+    // Each target's history is told of the estimate before any records timed by it,
+    // and of this source's horizon before anything it fetches - first, that nothing fetched starts before we did.
+    async fn fetch(&mut self, target: &Target) -> Result<Vec<Report<V>>, Error> {
+        let source = SourceKind::Record(self.platform.name().to_string());
+        let mut reports = Vec::new();
+        if self.registered.insert(target.clone()) {
+            reports.push(Report::Horizon(source.clone(), self.started));
+        }
+        if self.calibration_due() {
+            if let Err(e) = self.probe().await {
+                error!("{} - Failed to probe clock: {e}", self.platform.name());
+            }
+        }
+        let Some(deviation) = self.deviation else { return Ok(reports) };
+        if self.announced.insert(target.clone(), deviation) != Some(deviation) {
+            reports.push(Report::Deviation(source.clone(), deviation));
+        }
+
+        // Only changes certainly made after we started - the initial value already counts any before.
+        let start = self.started.saturating_offset(deviation.max);
+        let (since, _) = self.fetched.entry(target.clone()).or_insert((start, HashSet::new())).clone();
+        let sent = self.platform.now();
+        let changes = self.platform.changes(target, since).await?;
+        let replied = self.platform.now();
+        for (change, definition, timestamp) in changes {
+            // Changes at the latest timestamp are refetched - more may have been made at it since.
+            let (latest, at_latest) = self.fetched.get_mut(target).unwrap();
+            if timestamp < *latest || (timestamp == *latest && !at_latest.insert(change.clone())) {
+                continue;
+            }
+            if timestamp > *latest {
+                (*latest, *at_latest) = (timestamp, HashSet::from([change.clone()]));
+            }
+            reports.push(Report::Observed(Observation {
+                id: ObservationId::Change(source.clone(), change),
+                definition,
+                interval: deviation.interval(timestamp),
+                source: source.clone(),
+                compensates: None,
+                distribution: deviation.distribution(timestamp),
+                reported: Some(replied),
+                timestamp: Some(timestamp),
+            }));
+        }
+        // A change not yet fetched was made after this fetch was sent - stamped by a clock
+        // deviating anywhere within the estimate, its interval opens up to the estimate's width earlier.
+        let width = deviation.max.saturating_sub(deviation.min);
+        reports.push(Report::Horizon(source, sent.saturating_offset(width.saturating_neg())));
+        Ok(reports)
+    }
+
+    async fn write(&mut self, target: &Target, value: V) -> Result<(), Error> {
+        let sent = self.platform.now();
+        self.platform.write(target, value).await?;
+        let replied = self.platform.now();
+        self.polled.insert(target.clone(), (value, sent));
+        self.shared.send_modify(|shared| { shared.written.insert(target.clone(), Written { value, sent, replied }); });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, remove_file, write};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::sleep;
+    use uuid::Uuid;
+    use crate::inference::interval::{Deviation, Moment};
+    use crate::observations::{PollingInterpretation, Report};
+    use crate::observers::mocked::live::LivePlatform;
+    use crate::observers::mocked::poll_platform::{MockPlatform, MockPlatformConfig};
+    use crate::observers::mocked::record_platform::{MockRecordPlatform, MockRecordPlatformConfig};
+    use crate::observers::{Observer, PlatformAdapter};
+    use super::{drifted, Calibration, PlatformObserver};
+
+    // Each observer stands for a run - a restart must not make new polls repeat the IDs of journaled ones.
+    #[tokio::test(start_paused = true)]
+    async fn poll_ids_outlive_the_observer() {
+        let config = MockPlatformConfig { name: "Platform".to_string(), sale_lambda: 1e-9, edit_lambda: 0.0 }; // No sales.
+        let platform = Arc::new(LivePlatform::new(MockPlatform::new(config, 10), Duration::from_millis(40)));
+        let target = ("Location".to_string(), "Item".to_string());
+        let mut ids = Vec::new();
+        for value in [9, 8] {
+            let mut observer = PlatformObserver::new(platform.clone(), PollingInterpretation::Mutation);
+            observer.poll(&target).await.unwrap();
+            platform.write(&target, value).await.unwrap(); // Changed behind the observer's back.
+            for report in observer.poll(&target).await.unwrap() {
+                if let Report::Observed(observation) = report {
+                    ids.push(observation.id);
+                }
+            }
+        }
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn estimates_widen_as_clocks_may_drift() {
+        let deviation = Deviation { min: 0, max: 10 };
+        assert_eq!(drifted(deviation, Moment(0), Moment(0)), deviation);
+        assert_eq!(drifted(deviation, Moment(0), Moment(1)), Deviation { min: -1, max: 11 }); // Rounded up.
+        assert_eq!(drifted(deviation, Moment(0), Moment(1_000_000)), Deviation { min: -100, max: 110 });
+    }
+
+    // Kept from an earlier run, where the clock has since been stepped - never recalibrated, but probed afresh regardless.
+    #[tokio::test(start_paused = true)]
+    async fn resumed_estimates_are_probed_again() {
+        let path = std::env::temp_dir().join(format!("calibration-{}.json", Uuid::new_v4()));
+        let kept = Calibration { deviation: Deviation { min: 5000, max: 5010 }, probed: Moment(0) };
+        write(&path, serde_json::to_vec(&kept).unwrap()).unwrap();
+        let config = MockRecordPlatformConfig {
+            name: "Platform".to_string(), sale_lambda: 1e-9, edit_lambda: 0.0, deviation_lambda: 500.0, deviation_std_dev: 0.1, clock_precision: 1
+        };
+        let platform = LivePlatform::new(MockRecordPlatform::new(config, 10), Duration::from_millis(40));
+        let mut observer = PlatformObserver::new(platform, PollingInterpretation::Transition).calibrated(&path).unwrap();
+        assert_eq!(observer.share().borrow().deviation, Some(kept.deviation));
+
+        observer.fetch(&("Location".to_string(), "Item".to_string())).await.unwrap();
+        let deviation = observer.share().borrow().deviation.unwrap();
+        assert!(deviation.min <= 500 && 499 <= deviation.max && deviation.max - deviation.min <= 40, "Probed {deviation:?}");
+
+        // Kept in the background - the observer carries on meanwhile.
+        for _ in 0..100 {
+            let kept: Calibration = serde_json::from_slice(&read(&path).unwrap()).unwrap();
+            if kept.deviation == deviation {
+                return remove_file(&path).unwrap();
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{deviation:?} was not kept");
+    }
+}
@@ -1,23 +1,72 @@
-use std::env;
-use std::str::FromStr;
 use chrono::Utc;
+use log::{debug, error};
 use serde::{Deserialize, Serialize};
 use squareup::api::{CatalogApi, InventoryApi};
 use squareup::config::{BaseUri, Configuration, Environment};
 use squareup::http::client::HttpClientConfiguration;
-use squareup::models::enums::InventoryState::InStock;
-use squareup::models::errors::SquareApiError;
-use squareup::models::RetrieveInventoryCountParams;
+use squareup::http::Headers;
+use squareup::models::enums::{InventoryChangeType, InventoryState};
+use squareup::models::{BatchChangeInventoryRequest, BatchChangeInventoryResponse, BatchRetrieveInventoryChangesRequest, DateTime as SquareDateTime, InventoryChange, InventoryPhysicalCount, RetrieveInventoryCountParams};
 use squareup::SquareClient;
-use crate::value::{Target, Value};
+use uuid::Uuid;
+use crate::error::{Error, ObserverError, ParseError};
+use crate::inference::interval::Moment;
+use crate::observations::{DefinitionPredicate, PollingInterpretation};
+use crate::observers::{Change, PlatformAdapter};
+use crate::observers::webhook::SquareWebhookConfig;
+use crate::value::{Quantity, States, Stock, Target};
+
+// Reference ID our own writes are tagged with - they are consensus, not changes to observe.
+pub const IGNORE: &str = "IGNORE";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SquareObserverConfig {
-    pub(crate) token: String,
+    pub(crate) token: String, // This observer's own - observers may act for different merchants.
+    #[serde(default)]
+    pub(crate) environment: SquareEnvironment,
+    #[serde(default = "default_timeout")]
+    pub(crate) timeout: u32, // Seconds a request may take.
     pub(crate) target: String,
     pub(crate) calibration_target: String,
     pub(crate) location_id: String,
-    pub(crate) testing_config: SquareTestingConfig
+    pub(crate) testing_config: SquareTestingConfig,
+    #[serde(default)]
+    pub(crate) observe: SquareObserve,
+    #[serde(default = "default_every")]
+    pub(crate) every: u64, // Milliseconds between polls or fetches.
+    #[serde(default = "default_recalibrate")]
+    pub(crate) recalibrate: u64, // Seconds between probes of Square's clock - it drifts.
+    #[serde(default)]
+    pub(crate) webhook: Option<SquareWebhookConfig>, // Counts pushed as they change, alongside.
+}
+
+// How target's changes are found - by polling its count, or by fetching Square's records of them.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub enum SquareObserve {
+    Polling(PollingInterpretation),
+    #[default]
+    Records,
+}
+
+// Where Square is reached - a custom base URL points it at a local mock server.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub enum SquareEnvironment {
+    #[default]
+    Sandbox,
+    Production,
+    Custom(String),
+}
+
+fn default_timeout() -> u32 {
+    60
+}
+
+fn default_every() -> u64 {
+    1000
+}
+
+fn default_recalibrate() -> u64 {
+    600
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -29,46 +78,273 @@ pub struct SquareTestingConfig {
 pub struct SquareObserver {
     pub(crate) name: String,
     pub(crate) target: Target,
+    pub(crate) calibration: Target, // Written to read the server's clock - never observed.
     pub(crate) catalog_api: CatalogApi,
     pub(crate) inventory_api: InventoryApi,
 }
 
 impl SquareObserver {
-    pub fn new(name: String, config: SquareObserverConfig) -> SquareObserver {
-        // Set Auth Token (in config)
-        env::set_var("SQUARE_API_TOKEN", config.token);
-
-        // Initialise Catalog API
-        let catalog_api = CatalogApi::new(SquareClient::try_new(Configuration {
-            environment: Environment::Sandbox, // Testing in Sandbox Environment
-            http_client_config: HttpClientConfiguration::default(),
-            base_uri: BaseUri::default(),
-        }).unwrap());
+    pub fn new(name: String, config: SquareObserverConfig) -> Result<SquareObserver, Error> {
+        // Token goes in this client's own headers - never the process environment, which observers share.
+        let mut default_headers = Headers::default();
+        default_headers.insert("Authorization", &format!("Bearer {}", config.token));
+        let environment = match config.environment {
+            SquareEnvironment::Sandbox => Environment::Sandbox,
+            SquareEnvironment::Production => Environment::Production,
+            SquareEnvironment::Custom(url) => Environment::Custom(url),
+        };
 
-        // Initialise Inventory API
-        let inventory_api = InventoryApi::new(SquareClient::try_new(Configuration {
-            environment: Environment::Sandbox, // Testing in Sandbox Environment
-            http_client_config: HttpClientConfiguration::default(),
+        // One client shared by both APIs - and so one connection pool.
+        let client = SquareClient::try_new(Configuration {
+            environment,
+            http_client_config: HttpClientConfiguration { timeout: config.timeout, default_headers, ..Default::default() },
             base_uri: BaseUri::default(),
-        }).unwrap());
+        }).map_err(ObserverError::Client)?;
+        let catalog_api = CatalogApi::new(client.clone());
+        let inventory_api = InventoryApi::new(client);
 
-        return SquareObserver { name, catalog_api, inventory_api, target: (config.location_id, config.target) }
+        let calibration = (config.location_id.clone(), config.calibration_target);
+        return Ok(SquareObserver { name, catalog_api, inventory_api, target: (config.location_id, config.target), calibration })
     }
 
-    pub async fn request(&self, target: Target) -> Result<(Value, chrono::DateTime<Utc>, chrono::DateTime<Utc>), SquareApiError> {
+    pub async fn request_states<V: Quantity>(&self, target: Target) -> Result<(States<V>, chrono::DateTime<Utc>, chrono::DateTime<Utc>), Error> {
         let sent = chrono::Utc::now();
-        let response = self.inventory_api.retrieve_inventory_count(target.1, RetrieveInventoryCountParams {
-            location_ids: Some(vec![target.0]),
+        let response = self.inventory_api.retrieve_inventory_count(target.1.clone(), RetrieveInventoryCountParams {
+            location_ids: Some(vec![target.0.clone()]),
             cursor: None,
         }).await?;
         let replied = chrono::Utc::now();
 
-        let value: i64 = response.counts.expect("Target does not exist on platform!")
-            .iter()
-            .filter(|c| c.state == InStock)
-            .map(|c| Value::from_str(&c.quantity).expect("Failed to parse value from count!"))
-            .sum();
+        let mut states = States::<V>::zero();
+        for count in response.counts.ok_or(ObserverError::MissingCounts(target))? {
+            let Some(stock) = stock(&count.state) else {
+                debug!("Square - Ignoring count in untracked state {:?}", count.state);
+                continue;
+            };
+            let quantity = V::parse(&count.quantity).ok_or_else(|| ParseError::Quantity(count.quantity.clone()))?;
+            states[stock] = states[stock].combine(quantity).ok_or_else(|| ParseError::Overflow(format!("{:?} counts", count.state)))?;
+        }
+
+        return Ok((states, sent, replied));
+    }
+}
+
+// Every state Square counts - only what is in stock is written, other states change as stock moves.
+impl<Q: Quantity> PlatformAdapter<States<Q>> for SquareObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn now(&self) -> Moment {
+        moment(Utc::now())
+    }
+
+    async fn count(&self, target: &Target) -> Result<States<Q>, Error> {
+        Ok(self.request_states::<Q>(target.clone()).await?.0)
+    }
+
+    async fn write(&self, target: &Target, value: States<Q>) -> Result<(), Error> {
+        self.count_in_stock(target, value[Stock::InStock].decimal()).await?;
+        Ok(())
+    }
+
+    async fn changes(&self, target: &Target, since: Moment) -> Result<Vec<Change<States<Q>>>, Error> {
+        let mut request = BatchRetrieveInventoryChangesRequest {
+            catalog_object_ids: Some(vec![target.1.clone()]),
+            location_ids: Some(vec![target.0.clone()]),
+            types: None,
+            states: None,
+            updated_after: Some(SquareDateTime::from(&date_time(since))),
+            updated_before: None,
+            cursor: None,
+            limit: None,
+        };
 
-        return Ok((value, sent, replied));
+        let mut changes = Vec::new();
+        loop {
+            let response = self.inventory_api.batch_retrieve_inventory_changes(&request).await?;
+            for change in response.changes.unwrap_or_default() {
+                match parse_change(change) {
+                    Ok(Some(change)) => changes.push(change),
+                    Ok(None) => {}
+                    Err(e) => error!("{} - Skipping unreadable change: {e:?}", self.name), // Refetching would not fix it.
+                }
+            }
+            match response.cursor {
+                Some(cursor) => request.cursor = Some(cursor),
+                None => return Ok(changes),
+            }
+        }
     }
-}
\ No newline at end of file
+
+    // Square stamps what it is sent as it creates it - so the server's clock is read off a count of the calibration target.
+    async fn clock(&self) -> Result<Moment, Error> {
+        let response = self.count_in_stock(&self.calibration, Q::zero().decimal()).await?;
+        let created_at = response.changes.unwrap_or_default().into_iter()
+            .find_map(|change| change.physical_count?.created_at)
+            .ok_or_else(|| ParseError::Change("Calibration count was not stamped".to_string()))?;
+        Ok(moment(created_at.into()))
+    }
+}
+
+impl SquareObserver {
+    // Physical count of what target holds in stock, tagged as ours.
+    async fn count_in_stock(&self, target: &Target, quantity: String) -> Result<BatchChangeInventoryResponse, Error> {
+        Ok(self.inventory_api.batch_change_inventory(&BatchChangeInventoryRequest {
+            idempotency_key: Uuid::new_v4().to_string(),
+            changes: Some(vec![InventoryChange {
+                r#type: Some(InventoryChangeType::PhysicalCount),
+                physical_count: Some(InventoryPhysicalCount {
+                    id: None,
+                    reference_id: Some(IGNORE.to_string()),
+                    catalog_object_id: Some(target.1.clone()),
+                    catalog_object_type: None,
+                    state: Some(InventoryState::InStock),
+                    location_id: Some(target.0.clone()),
+                    quantity: Some(quantity),
+                    source: None,
+                    employee_id: None,
+                    team_member_id: None,
+                    occurred_at: Some(SquareDateTime::now()),
+                    created_at: None,
+                }),
+                adjustment: None,
+                transfer: None,
+                measurement_unit: None,
+                measurement_unit_id: None,
+            }]),
+            ignore_unchanged_counts: Some(false), // Unchanged counts are still stamped.
+        }).await?)
+    }
+}
+
+// A change as Square recorded it, stamped by the server's clock - None if it is ours, or does not move tracked stock.
+// Physical counts set a single state, adjustments move stock between two.
+pub fn parse_change<Q: Quantity>(change: InventoryChange) -> Result<Option<Change<States<Q>>>, ParseError> {
+    let missing = |field: &str| ParseError::Change(format!("{:?} change had no {field}", change.r#type));
+    let (id, reference_id, definition, created_at) = match change.r#type {
+        Some(InventoryChangeType::PhysicalCount) => {
+            let count = change.physical_count.clone().ok_or_else(|| missing("physical count"))?;
+            let quantity = count.quantity.ok_or_else(|| missing("quantity"))?;
+            let quantity = Q::parse(&quantity).ok_or(ParseError::Quantity(quantity))?;
+            let definition = stock(&count.state.ok_or_else(|| missing("state"))?)
+                .map(|stock| DefinitionPredicate::Count { stock, v_new: States::of(stock, quantity) });
+            (count.id, count.reference_id, definition, count.created_at)
+        }
+        Some(InventoryChangeType::Adjustment) => {
+            let moved = change.adjustment.clone().ok_or_else(|| missing("adjustment"))?;
+            let quantity = moved.quantity.ok_or_else(|| missing("quantity"))?;
+            let quantity = Q::parse(&quantity).ok_or(ParseError::Quantity(quantity))?;
+            let (from, to) = (moved.from_state.ok_or_else(|| missing("from state"))?, moved.to_state.ok_or_else(|| missing("to state"))?);
+            (moved.id, moved.reference_id, adjustment(&from, &to, quantity)?, moved.created_at)
+        }
+        _ => {
+            debug!("Square - Ignoring change of type {:?}", change.r#type);
+            return Ok(None);
+        }
+    };
+
+    // Tagged IGNORE - from the writers, so consensus rather than a change.
+    if reference_id.as_deref() == Some(IGNORE) {
+        return Ok(None);
+    }
+    let Some(definition) = definition else {
+        debug!("Square - Ignoring change to untracked states {change:?}");
+        return Ok(None);
+    };
+    let id = id.ok_or_else(|| missing("ID"))?;
+    let created_at = created_at.ok_or_else(|| missing("created date"))?;
+    Ok(Some((id, definition, moment(created_at.into()))))
+}
+
+pub(crate) fn moment(time: chrono::DateTime<Utc>) -> Moment {
+    Moment(time.timestamp_millis() as u64)
+}
+
+fn date_time(moment: Moment) -> chrono::DateTime<Utc> {
+    chrono::DateTime::from_timestamp_millis(moment.0 as i64).unwrap_or_default()
+}
+
+// Square's states we track - None for the rest.
+pub fn stock(state: &InventoryState) -> Option<Stock> {
+    match state {
+        InventoryState::InStock => Some(Stock::InStock),
+        InventoryState::Sold => Some(Stock::Sold),
+        InventoryState::Waste => Some(Stock::Waste),
+        InventoryState::InTransit => Some(Stock::InTransit),
+        _ => None,
+    }
+}
+
+// An adjustment moves quantity between states - None if it moves between untracked states only.
+pub fn adjustment<V: Quantity>(from: &InventoryState, to: &InventoryState, quantity: V) -> Result<Option<DefinitionPredicate<States<V>>>, ParseError> {
+    let (from, to) = (stock(from), stock(to));
+    if from.is_none() && to.is_none() {
+        return Ok(None);
+    }
+    let delta = States::moved(from, to, quantity).ok_or_else(|| ParseError::Overflow(format!("{quantity:?} moved")))?;
+    Ok(Some(DefinitionPredicate::Mutation { delta }))
+}
+
+#[cfg(test)]
+mod tests {
+    use squareup::models::enums::{InventoryChangeType, InventoryState};
+    use squareup::models::{DateTime as SquareDateTime, InventoryAdjustment, InventoryChange};
+    use crate::error::ParseError;
+    use crate::observations::DefinitionPredicate;
+    use crate::value::{Quantity, States, Stock};
+    use super::{adjustment, parse_change, IGNORE};
+
+    fn moved(from: InventoryState, to: InventoryState, quantity: &str, reference_id: Option<&str>) -> InventoryChange {
+        InventoryChange {
+            r#type: Some(InventoryChangeType::Adjustment),
+            physical_count: None,
+            adjustment: Some(InventoryAdjustment {
+                id: Some("Adjustment".to_string()),
+                reference_id: reference_id.map(str::to_string),
+                from_state: Some(from),
+                to_state: Some(to),
+                location_id: Some("Location".to_string()),
+                catalog_object_id: Some("Item".to_string()),
+                catalog_object_type: None,
+                quantity: Some(quantity.to_string()),
+                total_price_money: None,
+                occurred_at: None,
+                created_at: Some(SquareDateTime::now()),
+                source: None,
+                employee_id: None,
+                team_member_id: None,
+                transaction_id: None,
+                refund_id: None,
+                purchase_order_id: None,
+                goods_receipt_id: None,
+                adjustment_group: None,
+            }),
+            transfer: None,
+            measurement_unit: None,
+            measurement_unit_id: None,
+        }
+    }
+
+    #[test]
+    fn adjustments_move_between_states() {
+        let wasted = adjustment(&InventoryState::InStock, &InventoryState::Waste, 2i64).unwrap();
+        assert!(matches!(wasted, Some(DefinitionPredicate::Mutation { delta }) if delta == States::of(Stock::InStock, -2).combine(States::of(Stock::Waste, 2)).unwrap()));
+        let arrived = adjustment(&InventoryState::InTransit, &InventoryState::InStock, 3i64).unwrap();
+        assert!(matches!(arrived, Some(DefinitionPredicate::Mutation { delta }) if delta == States::of(Stock::InTransit, -3).combine(States::of(Stock::InStock, 3)).unwrap()));
+        // Returned, then written off before it was restocked - neither state is tracked.
+        assert!(adjustment(&InventoryState::ReturnedByCustomer, &InventoryState::None, 1i64).unwrap().is_none());
+    }
+
+    #[test]
+    fn changes_parse_to_what_they_moved() {
+        let (id, definition, _) = parse_change::<i64>(moved(InventoryState::InStock, InventoryState::Waste, "2", None)).unwrap().unwrap();
+        assert_eq!(id, "Adjustment");
+        assert!(matches!(definition, DefinitionPredicate::Mutation { delta } if delta[Stock::InStock] == -2 && delta[Stock::Waste] == 2 && delta[Stock::Sold] == 0));
+        let (_, definition, _) = parse_change::<i64>(moved(InventoryState::InTransit, InventoryState::InStock, "3", None)).unwrap().unwrap();
+        assert!(matches!(definition, DefinitionPredicate::Mutation { delta } if delta[Stock::InTransit] == -3 && delta[Stock::InStock] == 3));
+
+        assert!(parse_change::<i64>(moved(InventoryState::InStock, InventoryState::Waste, "2", Some(IGNORE))).unwrap().is_none()); // Ours.
+        assert!(matches!(parse_change::<i64>(moved(InventoryState::InStock, InventoryState::Waste, "two", None)), Err(ParseError::Quantity(_))));
+    }
+}
@@ -0,0 +1,309 @@
+use std::sync::Arc;
+use std::time::Duration;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use squareup::models::enums::InventoryState;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{watch, Mutex};
+use tokio::time::sleep;
+use crate::error::Error;
+use crate::inference::interval::{Deviation, Interval, Moment, TimeDistribution};
+use crate::observations::{DefinitionPredicate, Observation, ObservationId, Report, SourceKind};
+use crate::observers::platform::{PlatformObserver, Shared, Written};
+use crate::observers::square::{moment, stock, SquareObserver};
+use crate::value::{Quantity, States, Stock, Target};
+
+const SIGNATURE: &str = "x-square-hmacsha256-signature";
+const BODY_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SquareWebhookConfig {
+    pub(crate) address: String, // Listened on.
+    pub(crate) notification_url: String, // As registered with Square - part of what is signed.
+    pub(crate) signature_key: String,
+    #[serde(default = "default_max_delivery_delay")]
+    pub(crate) max_delivery_delay: u64, // Milliseconds a delivery may lag its count - any later is rejected once folded past.
+}
+
+fn default_max_delivery_delay() -> u64 {
+    60_000
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    r#type: String,
+    event_id: String,
+    data: EventData,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventData {
+    object: EventObject,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventObject {
+    #[serde(default)]
+    inventory_counts: Vec<EventCount>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventCount {
+    catalog_object_id: String,
+    location_id: String,
+    state: InventoryState,
+    quantity: String,
+    calculated_at: chrono::DateTime<Utc>,
+}
+
+// What every delivery to one Square account's webhook is handled with.
+struct Webhook<Q> {
+    config: SquareWebhookConfig,
+    shared: watch::Receiver<Shared<States<Q>>>, // Observer's clock estimate and last writes - read without locking it.
+    target: Target,
+    output: Sender<(Target, Report<States<Q>>)>,
+    source: SourceKind,
+}
+
+// Receives Square's inventory.count.updated webhooks - each count is pushed as it is calculated,
+// rather than found by the next poll. Counts go to the same channel as the pollers'.
+// Square's clock stamps the count - it is timed by the deviation the observer has probed.
+// Square pushes nothing while nothing changes - the horizon is reported every so often regardless, first before listening.
+pub async fn webhook_worker<Q: Quantity>(
+    config: SquareWebhookConfig,
+    observer: Arc<Mutex<PlatformObserver<States<Q>, SquareObserver>>>,
+    every: Duration,
+    output: Sender<(Target, Report<States<Q>>)>,
+) -> Result<(), Error> {
+    let (source, target, shared) = {
+        let observer = observer.lock().await;
+        (SourceKind::Push(observer.platform().name.clone()), observer.platform().target.clone(), observer.share())
+    };
+    let delay = Duration::from_millis(config.max_delivery_delay);
+    let report = move |source: &SourceKind| Report::Horizon(source.clone(), horizon(moment(Utc::now()), delay));
+    if output.send((target.clone(), report(&source))).await.is_err() {
+        return Ok(()); // Coordinator has gone.
+    }
+    let horizons = {
+        let (source, target, output) = (source.clone(), target.clone(), output.clone());
+        async move {
+            loop {
+                sleep(every).await;
+                if output.send((target.clone(), report(&source))).await.is_err() {
+                    return; // Coordinator has gone.
+                }
+            }
+        }
+    };
+
+    let listener = TcpListener::bind(&config.address).await?;
+    info!("{source:?} - Listening for webhooks on {}", config.address);
+    // Posted to the registered URL - on whatever path a proxy in front forwards it to.
+    let app = Router::new()
+        .fallback(post(receive::<Q>))
+        .layer(DefaultBodyLimit::max(BODY_LIMIT))
+        .with_state(Arc::new(Webhook { config, shared, target, output, source }));
+    tokio::select! {
+        served = axum::serve(listener, app) => served?,
+        () = horizons => {}
+    }
+    Ok(())
+}
+
+// Counts still to be delivered were counted no earlier than the longest a delivery may lag.
+fn horizon(now: Moment, max_delivery_delay: Duration) -> Moment {
+    Moment(now.0.saturating_sub(max_delivery_delay.as_millis() as u64))
+}
+
+// One delivery - anything Square should retry is answered as a failure.
+async fn receive<Q: Quantity>(State(webhook): State<Arc<Webhook<Q>>>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    let (config, source) = (&webhook.config, &webhook.source);
+    let received = moment(Utc::now());
+    let signature = headers.get(SIGNATURE).and_then(|signature| signature.to_str().ok());
+    if !verify(&config.signature_key, &config.notification_url, &body, signature) {
+        error!("{source:?} - Rejected a delivery whose signature did not verify");
+        return StatusCode::UNAUTHORIZED;
+    }
+    let event: Event = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(e) => {
+            error!("{source:?} - Could not read delivery: {e}");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if event.r#type != "inventory.count.updated" {
+        debug!("{source:?} - Ignoring webhook of type {}", event.r#type);
+        return StatusCode::OK;
+    }
+
+    let target = &webhook.target;
+    let (deviation, written) = {
+        let shared = webhook.shared.borrow();
+        (shared.deviation, shared.written.get(target).copied())
+    };
+    let Some(deviation) = deviation else {
+        info!("{source:?} - Clock not yet probed, so {} cannot be timed - Square will retry", event.event_id);
+        return StatusCode::SERVICE_UNAVAILABLE;
+    };
+    let mut observations = Vec::new();
+    for (i, count) in event.data.object.inventory_counts.into_iter().enumerate() {
+        let Some(stock) = stock(&count.state) else {
+            debug!("{source:?} - Ignoring count in untracked state {:?}", count.state);
+            continue;
+        };
+        let Some(quantity) = Q::parse(&count.quantity) else {
+            error!("{source:?} - Could not read quantity {} of {}", count.quantity, event.event_id);
+            return StatusCode::BAD_REQUEST;
+        };
+        let counted = (count.location_id, count.catalog_object_id);
+        let calculated = moment(count.calculated_at);
+        if !news(target, written, deviation, &counted, stock, quantity, calculated) {
+            debug!("{source:?} - Ignoring count of {counted:?} at {quantity:?} - not ours to observe, or our own write");
+            continue;
+        }
+        // Counted no earlier than its stamp allows, and no later than it arrived.
+        observations.push((counted, Observation {
+            id: ObservationId::Change(source.clone(), format!("{}-{i}", event.event_id)), // Retried deliveries repeat it.
+            definition: DefinitionPredicate::Count { stock, v_new: States::of(stock, quantity) },
+            interval: Interval(deviation.interval(calculated).0, received),
+            source: source.clone(),
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: Some(received),
+            timestamp: None, // Already corrected - a later deviation does not retime it.
+        }));
+    }
+
+    for (target, observation) in observations {
+        info!("{source:?} - New Observation: {:?}", observation);
+        if webhook.output.send((target, Report::Observed(observation))).await.is_err() {
+            return StatusCode::SERVICE_UNAVAILABLE; // Coordinator has gone.
+        }
+    }
+    StatusCode::OK
+}
+
+// Square pushes every count of the account - the calibration target's at each probe, and those our own writes made,
+// which webhooks deliver without the reference ID they are tagged with. Only the observed target's counts are news,
+// and of those, not one counting what we last wrote to it while we were writing it - the same count made later is.
+fn news<Q: Quantity>(
+    target: &Target,
+    written: Option<Written<States<Q>>>,
+    deviation: Deviation,
+    counted: &Target,
+    stock: Stock,
+    quantity: Q,
+    calculated: Moment
+) -> bool {
+    let echo = |written: Written<States<Q>>| {
+        written.value[Stock::InStock] == quantity && deviation.interval(calculated).intersects(&Interval(written.sent, written.replied))
+    };
+    counted == target && !(stock == Stock::InStock && written.is_some_and(echo))
+}
+
+// Square signs the notification URL followed by the body - base64 of its HMAC-SHA256.
+fn verify(key: &str, url: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let Some(signature) = signature.and_then(|signature| BASE64_STANDARD.decode(signature).ok()) else { return false };
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(url.as_bytes());
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok() // In constant time - timing reveals nothing of the expected signature.
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::inference::history::NewHistory;
+    use crate::inference::interval::{Deviation, Interval, Moment, TimeDistribution};
+    use crate::inference::policy::ConflictPolicyConfig;
+    use crate::inference::{Inference, Outcome};
+    use crate::observations::{DefinitionPredicate, Observation, ObservationId, SourceKind};
+    use crate::observers::platform::Written;
+    use crate::value::{States, Stock};
+    use super::{horizon, news, verify};
+
+    // Square's documented example notification.
+    const KEY: &str = "Ibxx_5AKakO-3qeNVR61Dw";
+    const URL: &str = "https://webhook.site/679a4f3a-dcfa-49ee-bac5-9d0edad886b9";
+    const BODY: &str = r#"{"merchant_id":"MLEFBHHSJGVHD","type":"webhooks.test_notification","event_id":"ac3ac95b-f97d-458c-a6e6-18981597e05f","created_at":"2022-07-13T20:30:59.037339943Z","data":{"type":"webhooks","id":"bc368e64-01aa-407e-b46e-3231809b1129"}}"#;
+    const SIGNATURE: &str = "GF4YkrJgGBDZ9NIYbNXBnMzqb2HoL4RW/S6vkZ9/2N4=";
+
+    #[test]
+    fn square_example_verifies() {
+        assert!(verify(KEY, URL, BODY.as_bytes(), Some(SIGNATURE)));
+    }
+
+    #[test]
+    fn anything_else_does_not() {
+        assert!(!verify(KEY, URL, BODY.replace("MLEFBHHSJGVHD", "MLEFBHHSJGVHE").as_bytes(), Some(SIGNATURE)));
+        assert!(!verify(KEY, "https://webhook.site/elsewhere", BODY.as_bytes(), Some(SIGNATURE)));
+        assert!(!verify("another key", URL, BODY.as_bytes(), Some(SIGNATURE)));
+        assert!(!verify(KEY, URL, BODY.as_bytes(), Some("GF4YkrJgGBDZ9NIYbNXBnMzqb2HoL4RW/S6vkZ9/2N5=")));
+        assert!(!verify(KEY, URL, BODY.as_bytes(), Some("not base64")));
+        assert!(!verify(KEY, URL, BODY.as_bytes(), None));
+    }
+
+    // Written at 5 between 100 and 140 - by a clock within 10 of ours.
+    #[test]
+    fn echoes_and_other_targets_are_not_news() {
+        let target = ("Location".to_string(), "Item".to_string());
+        let calibration = ("Location".to_string(), "Calibration".to_string());
+        let written = Some(Written { value: States::of(Stock::InStock, 5i64), sent: Moment(100), replied: Moment(140) });
+        let deviation = Deviation { min: -10, max: 10 };
+        assert!(!news(&target, written, deviation, &target, Stock::InStock, 5, Moment(125))); // Our own write, come back.
+        assert!(news(&target, written, deviation, &target, Stock::InStock, 4, Moment(125)));
+        assert!(news(&target, written, deviation, &target, Stock::Sold, 5, Moment(125)));
+        assert!(news(&target, None, deviation, &target, Stock::InStock, 5, Moment(125)));
+        assert!(!news(&target, written, deviation, &calibration, Stock::InStock, 0, Moment(125))); // A probe's count.
+
+        // Restocked to 6, then sold back down to 5 - the same count as written, made since.
+        assert!(news(&target, written, deviation, &target, Stock::InStock, 6, Moment(1000)));
+        assert!(news(&target, written, deviation, &target, Stock::InStock, 5, Moment(2000)));
+    }
+
+    // Polled up to 1000 - but a count made at 600 may still be delivered, so only what came before 500 is folded.
+    #[test]
+    fn delayed_deliveries_are_accepted_after_a_fold() {
+        let (polling, push) = (SourceKind::Polling("Square".to_string()), SourceKind::Push("Square".to_string()));
+        let mut history = NewHistory::new(ConflictPolicyConfig::Strict.build());
+        history.expect(polling.clone());
+        history.expect(push.clone());
+        let sale = Observation {
+            id: ObservationId::Poll(polling.clone(), Moment(100)),
+            definition: DefinitionPredicate::Mutation { delta: -1 },
+            interval: Interval(Moment(100), Moment(200)),
+            source: polling.clone(),
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: Some(Moment(200)),
+            timestamp: None,
+        };
+        history.add_new(sale).unwrap();
+        history.advance(polling, Moment(1000)).unwrap();
+        history.advance(push.clone(), horizon(Moment(1000), Duration::from_millis(500))).unwrap();
+        assert_eq!(history.snapshot(Some(10)).map(|s| (s.value, s.folded_until)), Some((Some(9), Some(Moment(500)))));
+
+        let delivered = Observation {
+            id: ObservationId::Change(push.clone(), "Event-0".to_string()),
+            definition: DefinitionPredicate::Assignment { v_new: 7 },
+            interval: Interval(Moment(600), Moment(1050)),
+            source: push,
+            compensates: None,
+            distribution: TimeDistribution::Uniform,
+            reported: Some(Moment(1050)),
+            timestamp: None,
+        };
+        assert_eq!(history.add_new(delivered).unwrap(), Outcome::Accepted);
+        assert_eq!(history.apply(Some(10)), Some(7));
+    }
+}
@@ -1,4 +1,5 @@
-use rand::prelude::{Distribution, ThreadRng};
+use rand::Rng;
+use rand::prelude::Distribution;
 use rand_distr::{Exp, Normal};
 use rand_distr::num_traits::ToPrimitive;
 use crate::observations::{DefinitionPredicate, Tick};
@@ -6,14 +7,155 @@ use crate::observations::{DefinitionPredicate, Tick};
 pub type Lambda = f64;
 pub type Event = (DefinitionPredicate, Tick);
 
-pub fn exp(lambda: Lambda, rng: &mut ThreadRng) -> Tick {
+pub fn exp(lambda: Lambda, rng: &mut impl Rng) -> Tick {
     Exp::new(lambda).unwrap().sample(rng).to_u64().unwrap()
 }
 
-pub fn norm(lambda: Lambda, std_dev: Lambda, rng: &mut ThreadRng) -> Tick {
+pub fn norm(lambda: Lambda, std_dev: Lambda, rng: &mut impl Rng) -> Tick {
     Normal::new(lambda, std_dev).unwrap().sample(rng).to_u64().unwrap()
 }
 
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cmp::max;
+    use rand::{rng, Rng};
+    use crate::inference::history::{History, NewHistory};
+    use crate::inference::Inference;
+    use crate::inference::interval::{Deviation, Interval, Moment, TimeDistribution};
+    use crate::inference::policy::ConflictPolicyConfig;
+    use crate::observations::{DefinitionPredicate, Observation, ObservationId, Retraction, SourceKind, Tick};
+
+    // Random observation stream - consecutive polls per polling source, scattered records otherwise.
+    // Sources are numbered per platform - the first polling and first record sources watch the same one.
+    // Some observations are delivered again later, as an overlapping refetch would.
+    pub(crate) fn random_observations(length: usize, polling_sources: usize, record_sources: usize, rng: &mut impl Rng) -> Vec<Observation> {
+        let mut last_polls: Vec<(Tick, Tick)> = vec![(0, 0); polling_sources];
+        let mut build: Vec<Observation> = Vec::with_capacity(length);
+
+        for sequence in 0..length as u64 {
+            if !build.is_empty() && rng.random_ratio(1, 10) {
+                build.push(build[rng.random_range(0..build.len())].clone());
+                continue;
+            }
+            let source = rng.random_range(0..(polling_sources + record_sources));
+            let definition = match rng.random_range(0..6) {
+                0 => DefinitionPredicate::Assignment { v_new: rng.random_range(8..12) },
+                1 => DefinitionPredicate::Transition { v_0: rng.random_range(8..12), v_1: rng.random_range(8..12) },
+                _ => DefinitionPredicate::Mutation { delta: rng.random_range(-2..3) },
+            };
+
+            if source < polling_sources {
+                // Polls from one source are sent in order - both ends of their intervals advance.
+                let (last_sent, last_replied) = last_polls[source];
+                let sent = last_sent + rng.random_range(1..20);
+                let replied = max(sent, last_replied) + rng.random_range(1..20);
+                last_polls[source] = (sent, replied);
+                let source = SourceKind::Polling(format!("Platform {source}"));
+                build.push(Observation {
+                    id: ObservationId::Sequence(source.clone(), sequence),
+                    definition,
+                    interval: Interval(Moment(last_sent), Moment(replied)),
+                    source,
+                    compensates: None,
+                    distribution: TimeDistribution::Uniform,
+                    reported: Some(Moment(replied)),
+                    timestamp: None,
+                });
+            } else {
+                // Some records offset an earlier change - and cannot have happened before it.
+                let compensated = (!build.is_empty() && rng.random_ratio(1, 5)).then(|| &build[rng.random_range(0..build.len())]);
+                let start = max(rng.random_range(0..(length as Tick * 10)), compensated.map_or(0, |c| c.interval.0.0));
+                let end = start + rng.random_range(0..20);
+                let source = SourceKind::Record(format!("Platform {}", source - polling_sources));
+                build.push(Observation {
+                    id: ObservationId::Change(source.clone(), format!("{sequence}")),
+                    definition,
+                    interval: Interval(Moment(start), Moment(end)),
+                    source,
+                    compensates: compensated.map(|c| c.id.clone()),
+                    distribution: TimeDistribution::Uniform,
+                    reported: Some(Moment(rng.random_range(max(start, end.saturating_sub(5))..=end + 10))), // May cut its interval short.
+                    timestamp: Some(Moment(end)),
+                });
+            }
+        }
+        return build;
+    }
+
+    // Feeds the same random streams to two inference backends, checking they agree after every observation.
+    // Some observations are retracted along the way - possibly before they arrive - recorders' clocks re-estimated,
+    // and sources' horizons advanced so that both fold - rejecting alike whatever then arrives behind the fold.
+    fn differential<A: Inference, B: Inference>(trials: usize, length: usize, new_a: impl Fn() -> A, new_b: impl Fn() -> B) {
+        let mut rng = rng();
+        for trial in 0..trials {
+            let (mut a, mut b) = (new_a(), new_b());
+            let init = Some(10);
+            let observations = random_observations(length, 2, 2, &mut rng);
+
+            for (sequence, observation) in observations.iter().enumerate() {
+                let (added_a, added_b) = (a.add_new(observation.clone()), b.add_new(observation.clone()));
+                assert_eq!(added_a.as_ref().ok(), added_b.as_ref().ok(), "Backends disagree on accepting {observation:?}");
+                if rng.random_ratio(1, 10) {
+                    let retracted = &observations[rng.random_range(0..observations.len())];
+                    let retraction = Retraction {
+                        id: ObservationId::Sequence(SourceKind::Record("Retractor".to_string()), sequence as u64),
+                        retracts: retracted.id.clone(),
+                        source: SourceKind::Record("Retractor".to_string()),
+                        reason: "Voided".to_string(),
+                    };
+                    let (retracted_a, retracted_b) = (a.retract(retraction.clone()), b.retract(retraction));
+                    assert_eq!(retracted_a.as_ref().ok(), retracted_b.as_ref().ok(), "Backends disagree on retracting {retracted:?}");
+                }
+                if rng.random_ratio(1, 10) {
+                    // A recorder's clock estimate is revised - what it stamped is retimed.
+                    let source = SourceKind::Record(format!("Platform {}", rng.random_range(0..2)));
+                    let min = rng.random_range(-5..=0);
+                    let deviation = Deviation { min, max: min + rng.random_range(10..20) }; // Never past its reply.
+                    let (retimed_a, retimed_b) = (a.deviation(source.clone(), deviation), b.deviation(source, deviation));
+                    assert_eq!(retimed_a.is_ok(), retimed_b.is_ok(), "Backends disagree on retiming by {deviation:?}");
+                }
+                if rng.random_ratio(1, 5) {
+                    // Every source promises nothing more well behind what it has delivered - both backends fold.
+                    let horizon = Moment((sequence as Tick * 10).saturating_sub(length as Tick * 5));
+                    let sources = ["Platform 0", "Platform 1"].into_iter()
+                        .flat_map(|platform| [SourceKind::Polling(platform.to_string()), SourceKind::Record(platform.to_string())]);
+                    for source in sources {
+                        let (advanced_a, advanced_b) = (a.advance(source.clone(), horizon), b.advance(source, horizon));
+                        assert_eq!(advanced_a.is_ok(), advanced_b.is_ok(), "Backends disagree on advancing to {horizon:?}");
+                    }
+                }
+                let (value_a, value_b) = (a.apply(init), b.apply(init));
+                assert_eq!(value_a, value_b, "Backends disagree on trial {trial} after {observation:?}");
+                assert_eq!(a.possible(init), b.possible(init), "Backends disagree on possible values on trial {trial}");
+                assert_eq!(a.duplicates(), b.duplicates(), "Backends disagree on duplicates on trial {trial}");
+                let moment = Moment(rng.random_range(0..(length as Tick * 10)));
+                assert_eq!(a.value_at(init, moment), b.value_at(init, moment), "Backends disagree at {moment:?} on trial {trial}");
+            }
+        }
+    }
+
+    #[test]
+    fn backends_agree_last_end_wins() {
+        differential(20, 50, || NewHistory::new(ConflictPolicyConfig::LastEndWins.build()), || History::new(ConflictPolicyConfig::LastEndWins.build()));
+    }
+
+    #[test]
+    fn backends_agree_strict() {
+        differential(20, 50, || NewHistory::new(ConflictPolicyConfig::Strict.build()), || History::new(ConflictPolicyConfig::Strict.build()));
+    }
+
+    #[test]
+    fn backends_agree_pessimistic_minimum() {
+        differential(20, 50, || NewHistory::new(ConflictPolicyConfig::PessimisticMinimum.build()), || History::new(ConflictPolicyConfig::PessimisticMinimum.build()));
+    }
+
+    #[test]
+    fn backends_agree_most_likely() {
+        let policy = ConflictPolicyConfig::MostLikely { samples: 50 };
+        differential(10, 30, || NewHistory::new(policy.build()), || History::new(policy.build()));
+    }
+}
+
 
 
 
@@ -1,5 +1,295 @@
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::{Index, IndexMut};
+use std::str::FromStr;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
-
-// #[derive(Clone, Debug, PartialEq)]
+// Default value domain - whole item counts.
 pub type Value = i64;
-pub type Target = (String, String);
\ No newline at end of file
+pub type Target = (String, String);
+
+// A value domain the inference engine can reason over.
+// Mutations combine by the group operation, transitions compare by equality, assignments overwrite.
+// Counts overwrite only the state counted - a bare quantity is all one state.
+// The group operation is checked - None where the result leaves the domain's range.
+pub trait Quantity: Copy + Debug + Ord + Hash + Send + Sync + Serialize + DeserializeOwned + 'static {
+    fn zero() -> Self; // Identity - a mutation which changes nothing.
+    fn combine(self, other: Self) -> Option<Self>;
+    fn inverse(self) -> Option<Self>;
+    // Parses a platform's decimal quantity - None if it cannot be represented exactly.
+    fn parse(text: &str) -> Option<Self>;
+    // As a platform's decimal quantity - what parse reads back.
+    fn decimal(self) -> String;
+
+    // Mutation taking other to self.
+    fn difference(self, other: Self) -> Option<Self> {
+        self.combine(other.inverse()?)
+    }
+
+    // Self with stock counted as in count - the rest left as it was.
+    fn counted(self, _stock: Stock, count: Self) -> Self {
+        count
+    }
+}
+
+impl Quantity for i64 {
+    fn zero() -> Self {
+        0
+    }
+
+    fn combine(self, other: Self) -> Option<Self> {
+        self.checked_add(other)
+    }
+
+    fn inverse(self) -> Option<Self> {
+        self.checked_neg()
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        i64::from_str(text).ok()
+    }
+
+    fn decimal(self) -> String {
+        self.to_string()
+    }
+}
+
+// Fixed point decimal with PLACES digits after the point - exact for weights and lengths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Fixed<const PLACES: u32>(pub i64); // In units of 10^-PLACES.
+
+impl<const PLACES: u32> Quantity for Fixed<PLACES> {
+    fn zero() -> Self {
+        Fixed(0)
+    }
+
+    fn combine(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Fixed)
+    }
+
+    fn inverse(self) -> Option<Self> {
+        self.0.checked_neg().map(Fixed)
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > PLACES as usize || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None; // More precision than we can hold.
+        }
+        let scale = Self::scale()?;
+        let whole = if whole.is_empty() { 0 } else { u128::from_str(whole).ok()? };
+        let padded = format!("{fraction:0<width$}", width = PLACES as usize);
+        let fraction = if padded.is_empty() { 0 } else { u128::from_str(&padded).ok()? };
+        let units = i128::try_from(whole.checked_mul(scale)?.checked_add(fraction)?).ok()?;
+        i64::try_from(if negative { -units } else { units }).ok().map(Fixed)
+    }
+
+    fn decimal(self) -> String {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs() as u128;
+        let (whole, fraction) = match Self::scale() {
+            Some(scale) => (units / scale, units % scale),
+            None => (0, units) // Any i64 is wholly fraction long before then.
+        };
+        match PLACES {
+            0 => format!("{sign}{units}"),
+            _ => format!("{sign}{whole}.{fraction:0width$}", width = PLACES as usize)
+        }
+    }
+}
+
+impl<const PLACES: u32> Fixed<PLACES> {
+    // 10^PLACES, shared by parse and decimal - None once past u128.
+    fn scale() -> Option<u128> {
+        10u128.checked_pow(PLACES)
+    }
+}
+
+// Floating point quantity - ordered and hashed by bit pattern, as BTreeSet and HashMap need.
+// Sums are only as exact as f64 allows, so mutations in different orders may not agree exactly.
+// A sum too large to be finite has left the range.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl Quantity for Float {
+    fn zero() -> Self {
+        Float(0.0)
+    }
+
+    fn combine(self, other: Self) -> Option<Self> {
+        Some(Float(self.0 + other.0)).filter(|sum| sum.0.is_finite())
+    }
+
+    fn inverse(self) -> Option<Self> {
+        Some(Float(-self.0))
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        f64::from_str(text).ok().filter(|v| v.is_finite()).map(Float)
+    }
+
+    fn decimal(self) -> String {
+        self.0.to_string()
+    }
+}
+
+// States inventory can be held in - each tracked separately by States.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Stock {
+    InStock,
+    Sold,
+    Waste,
+    InTransit,
+}
+
+pub const STOCKS: [Stock; 4] = [Stock::InStock, Stock::Sold, Stock::Waste, Stock::InTransit];
+
+// Quantity held in each state - a move between states is a mutation taking from one, adding to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct States<Q = Value>(pub [Q; 4]); // Indexed in the order of STOCKS.
+
+impl<Q: Quantity> States<Q> {
+    pub fn of(stock: Stock, quantity: Q) -> Self {
+        let mut states = Self::zero();
+        states[stock] = quantity;
+        states
+    }
+
+    // Quantity moved out of one state and into another - either may be untracked.
+    pub fn moved(from: Option<Stock>, to: Option<Stock>, quantity: Q) -> Option<Self> {
+        let mut states = Self::zero();
+        if let Some(from) = from {
+            states[from] = states[from].combine(quantity.inverse()?)?;
+        }
+        if let Some(to) = to {
+            states[to] = states[to].combine(quantity)?;
+        }
+        Some(states)
+    }
+}
+
+impl<Q> Index<Stock> for States<Q> {
+    type Output = Q;
+
+    fn index(&self, stock: Stock) -> &Q {
+        &self.0[stock as usize]
+    }
+}
+
+impl<Q> IndexMut<Stock> for States<Q> {
+    fn index_mut(&mut self, stock: Stock) -> &mut Q {
+        &mut self.0[stock as usize]
+    }
+}
+
+impl<Q: Quantity> Quantity for States<Q> {
+    fn zero() -> Self {
+        States([Q::zero(); 4])
+    }
+
+    fn combine(self, other: Self) -> Option<Self> {
+        let mut combined = self;
+        for stock in STOCKS {
+            combined[stock] = self[stock].combine(other[stock])?;
+        }
+        Some(combined)
+    }
+
+    fn inverse(self) -> Option<Self> {
+        let mut inverse = self;
+        for stock in STOCKS {
+            inverse[stock] = self[stock].inverse()?;
+        }
+        Some(inverse)
+    }
+
+    // A bare quantity is a count of what is in stock.
+    fn parse(text: &str) -> Option<Self> {
+        Some(States::of(Stock::InStock, Q::parse(text)?))
+    }
+
+    // Only what is in stock can be given as a bare quantity.
+    fn decimal(self) -> String {
+        self[Stock::InStock].decimal()
+    }
+
+    fn counted(self, stock: Stock, count: Self) -> Self {
+        let mut counted = self;
+        counted[stock] = count[stock];
+        counted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fixed, Float, Quantity, States, Stock};
+
+    #[test]
+    fn combining_past_the_range_is_none() {
+        assert_eq!(i64::MAX.combine(1), None);
+        assert_eq!(i64::MIN.inverse(), None);
+        assert_eq!(5i64.difference(i64::MIN), None);
+        assert_eq!(Fixed::<3>(i64::MIN).combine(Fixed(-1)), None);
+        assert_eq!(Float(f64::MAX).combine(Float(f64::MAX)), None);
+        assert_eq!(States::<i64>::moved(Some(Stock::InStock), Some(Stock::Sold), i64::MIN), None);
+        assert_eq!(States::of(Stock::Sold, i64::MAX).combine(States::of(Stock::Sold, 1)), None);
+        assert_eq!(10i64.combine(-3), Some(7));
+    }
+
+    #[test]
+    fn fixed_parses_what_it_prints() {
+        assert_eq!(Fixed::<3>::parse("1.5"), Some(Fixed(1500)));
+        assert_eq!(Fixed::<3>::parse("-.001"), Some(Fixed(-1)));
+        assert_eq!(Fixed::<3>::parse("2.10000"), Some(Fixed(2100))); // Trailing zeros hold no precision.
+        assert_eq!(Fixed::<3>::parse("1.2345"), None);
+        assert_eq!(Fixed::<3>::parse("1.x"), None);
+        assert_eq!(Fixed::<3>::parse("."), None);
+        assert_eq!(Fixed::<0>::parse("12"), Some(Fixed(12)));
+        assert_eq!(Fixed::<19>::parse("0.5"), Some(Fixed(5_000_000_000_000_000_000)));
+        assert_eq!(Fixed::<19>::parse("1"), None); // Past the range.
+        assert_eq!(Fixed::<20>(-5).decimal(), "-0.00000000000000000005");
+
+        for value in [0, 1, -1, 1500, i64::MAX, i64::MIN] {
+            assert_eq!(Fixed::<0>::parse(&Fixed::<0>(value).decimal()), Some(Fixed(value)));
+            assert_eq!(Fixed::<3>::parse(&Fixed::<3>(value).decimal()), Some(Fixed(value)));
+            assert_eq!(Fixed::<19>::parse(&Fixed::<19>(value).decimal()), Some(Fixed(value)));
+            assert_eq!(Fixed::<25>::parse(&Fixed::<25>(value).decimal()), Some(Fixed(value)));
+        }
+    }
+}